@@ -0,0 +1,61 @@
+//! Times the phases of a grammar and compares the incremental cell maintenance of a step with
+//! rebuilding all cells, as it was done before.
+//!
+//! `cargo run --release --example step_timings -- <some.json> [steps] [seed]`
+
+use std::time::{Duration, Instant};
+
+use crustswarm_lib::io::genome_from_file;
+use crustswarm_lib::swarm::{grammar::SwarmGrammar, stats::StepStats, world::World};
+use rand::{rngs::StdRng, SeedableRng};
+
+/// Phase timings summed over all steps, the time spent rebuilding the cells after every step
+/// if `rebuild` is set, and the summed agent counts.
+fn run(path: &str, steps: u32, seed: u64, rebuild: bool) -> (StepStats, Duration, usize) {
+    let mut rnd = StdRng::seed_from_u64(seed);
+    let mut grammar = SwarmGrammar::from(genome_from_file(path), &mut rnd);
+    let mut total = StepStats::default();
+    let mut rebuild_time = Duration::default();
+    let mut agents = 0;
+
+    for _ in 0..steps {
+        let stats = grammar.step(&mut rnd);
+        total.replacement_time += stats.replacement_time;
+        total.movement_time += stats.movement_time;
+        total.cells_time += stats.cells_time;
+        total.fields_time += stats.fields_time;
+        total.terrain_time += stats.terrain_time;
+        total.erosion_time += stats.erosion_time;
+        agents += grammar.world.get_agent_count();
+
+        if rebuild {
+            // The previous cell maintenance: collect, sort and reinsert every agent
+            let start = Instant::now();
+            let mut all: Vec<_> = grammar.world.get_all_agents().cloned().collect();
+            all.sort_by_key(|agent| agent.id);
+            grammar.world.set_agents(all);
+            rebuild_time += start.elapsed();
+        }
+    }
+    (total, rebuild_time, agents)
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().expect("Genome to time required!");
+    let steps = args.next().map_or(100, |s| s.parse::<u32>().unwrap());
+    let seed = args.next().map_or(0, |s| s.parse::<u64>().unwrap());
+
+    let (total, _, agents) = run(&path, steps, seed, false);
+    // Rebuilding reorders the agents, so this run only matches the first one statistically
+    let (_, rebuild_time, _) = run(&path, steps, seed, true);
+
+    println!("{} steps, {} agents on average", steps, agents / steps as usize);
+    println!("replacement {:>10.1?}", total.replacement_time / steps);
+    println!("movement    {:>10.1?}", total.movement_time / steps);
+    println!("cells       {:>10.1?}", total.cells_time / steps);
+    println!("fields      {:>10.1?}", total.fields_time / steps);
+    println!("terrain     {:>10.1?}", total.terrain_time / steps);
+    println!("erosion     {:>10.1?}", total.erosion_time / steps);
+    println!("rebuild     {:>10.1?}", rebuild_time / steps);
+}
@@ -2,10 +2,14 @@ use compression::prelude::{DecodeExt, Deflater, EncodeExt, Inflater};
 
 use crate::swarm::evo::genome::OIDESwarmGenome;
 use crate::swarm::genome::dummies::DummySwarmGenome;
-use crate::swarm::genome::SwarmGenome;
+use crate::swarm::genome::obstacle::ObstacleConfig;
+use crate::swarm::genome::terrain::Heightmap;
+use crate::swarm::genome::{GenomeFiles, SwarmGenome};
 use crate::swarm::grammar::SwarmGrammar;
 use crate::swarm::world::World;
 
+use cgmath::{InnerSpace, Vector3};
+
 use std::fs::File;
 use std::io::BufWriter;
 use std::io::Error;
@@ -26,8 +30,57 @@ pub fn genome_from_file(path: impl AsRef<Path>) -> SwarmGenome {
         .unwrap();
     let mut json_str = String::new();
     file.read_to_string(&mut json_str).unwrap();
-    let foo: DummySwarmGenome = serde_json::from_str(&json_str).unwrap();
-    SwarmGenome::try_from(foo).unwrap()
+    let mut foo: DummySwarmGenome = serde_json::from_str(&json_str).unwrap();
+    if let (Some(heightmap), Some(dir)) = (&mut foo.terrain.heightmap, path.as_ref().parent()) {
+        heightmap.path = dir.join(&heightmap.path);
+    }
+    if let Some(dir) = path.as_ref().parent() {
+        for obstacle in foo.obstacles.iter_mut() {
+            if let ObstacleConfig::Mesh { path, .. } = obstacle {
+                *path = dir.join(&path);
+            }
+        }
+    }
+    let files = genome_files(&foo).unwrap();
+    SwarmGenome::try_from((foo, files)).unwrap()
+}
+
+/// Loads the heightmap and obstacle meshes a genome refers to, with paths as they are given.
+pub fn genome_files(dummy: &DummySwarmGenome) -> Result<GenomeFiles, String> {
+    let mut files = GenomeFiles::default();
+    if let Some(config) = &dummy.terrain.heightmap {
+        files.heightmap = Some(heightmap_from_file(&config.path)?);
+    }
+    for obstacle in &dummy.obstacles {
+        if let ObstacleConfig::Mesh { path, .. } = obstacle {
+            let text = fs::read_to_string(path).map_err(|e| {
+                format!(
+                    "Error while opening obstacle mesh from {}! \nError: {}",
+                    path.display(),
+                    e
+                )
+            })?;
+            files.meshes.insert(path.clone(), text);
+        }
+    }
+    Ok(files)
+}
+
+/// Reads a heightmap from a `.pgm` or `.csv` file.
+pub fn heightmap_from_file(path: impl AsRef<Path>) -> Result<Heightmap, String> {
+    let path = path.as_ref();
+    let bytes = fs::read(path).map_err(|e| {
+        format!(
+            "Error while opening heightmap from {}! \nError: {}",
+            path.display(),
+            e
+        )
+    })?;
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("pgm") => Heightmap::from_pgm(&bytes),
+        Some("csv") => Heightmap::from_csv(&String::from_utf8_lossy(&bytes)),
+        _ => Err(format!("Unknown heightmap format of {}", path.display())),
+    }
 }
 
 pub fn raw_genome_from_file(path: impl AsRef<Path>) -> SwarmGenome {
@@ -87,7 +140,9 @@ pub fn grammar_from_file(path: impl AsRef<Path>) -> SwarmGrammar {
         .unwrap();
     let decompressed = String::from_utf8(decompressed).unwrap();
     //fs::write("decompressed.grammar.json", &decompressed).unwrap_or(());
-    serde_json::from_str(&decompressed).unwrap()
+    let mut grammar: SwarmGrammar = serde_json::from_str(&decompressed).unwrap();
+    grammar.world.rechunk();
+    grammar
 }
 
 pub fn grammar_to_file(template: &SwarmGrammar, path: impl AsRef<Path>) -> Option<Error> {
@@ -122,3 +177,210 @@ pub fn print_swarm(world: &impl World, writer: &mut BufWriter<File>) {
         write!(writer,"{} {} {}\n",x ,y ,z).unwrap();
     }*/
 }
+
+/// Terrain heights sampled on a regular grid, row by row along x.
+struct TerrainSamples {
+    width: usize,
+    depth: usize,
+    positions: Vec<Vector3<f32>>,
+    normals: Vec<Vector3<f32>>,
+}
+
+impl TerrainSamples {
+    /// Samples the terrain at every buoy and `subdivisions - 1` interpolated points in between.
+    fn new(world: &impl World, subdivisions: usize) -> TerrainSamples {
+        let (x_size, z_size, spacing, origin) = world.get_size();
+        let subdivisions = subdivisions.max(1);
+        let step = spacing / subdivisions as f32;
+        let width = (x_size - 1) * subdivisions + 1;
+        let depth = (z_size - 1) * subdivisions + 1;
+        let min_x = origin.0 - ((x_size - 1) / 2) as f32 * spacing;
+        let min_z = origin.1 - ((z_size - 1) / 2) as f32 * spacing;
+
+        let mut positions = Vec::with_capacity(width * depth);
+        let mut normals = Vec::with_capacity(width * depth);
+        for z in 0..depth {
+            for x in 0..width {
+                let (xpos, zpos) = (min_x + x as f32 * step, min_z + z as f32 * step);
+                let offset = step * 0.5;
+                let slope_x = (world.get_height_at(xpos + offset, zpos)
+                    - world.get_height_at(xpos - offset, zpos))
+                    / step;
+                let slope_z = (world.get_height_at(xpos, zpos + offset)
+                    - world.get_height_at(xpos, zpos - offset))
+                    / step;
+
+                positions.push(Vector3::new(xpos, world.get_height_at(xpos, zpos), zpos));
+                normals.push(Vector3::new(-slope_x, 1.0, -slope_z).normalize());
+            }
+        }
+
+        TerrainSamples {
+            width,
+            depth,
+            positions,
+            normals,
+        }
+    }
+
+    /// Counter-clockwise triangles seen from above, two per grid square.
+    fn triangles(&self) -> Vec<[usize; 3]> {
+        let mut triangles = Vec::with_capacity(2 * self.width * self.depth);
+        for z in 1..self.depth {
+            for x in 1..self.width {
+                let (a, b) = ((z - 1) * self.width + x - 1, (z - 1) * self.width + x);
+                let (c, d) = (z * self.width + x - 1, z * self.width + x);
+                triangles.push([a, c, b]);
+                triangles.push([b, c, d]);
+            }
+        }
+        triangles
+    }
+}
+
+/// Writes the terrain heights as 16-bit PGM, stretched from the lowest to the highest point.
+/// The stretched height range is noted in a comment of the header.
+pub fn terrain_to_pgm(
+    world: &impl World,
+    subdivisions: usize,
+    path: impl AsRef<Path>,
+) -> Option<Error> {
+    let samples = TerrainSamples::new(world, subdivisions);
+    let heights = samples.positions.iter().map(|pos| pos.y);
+    let min = heights.clone().fold(f32::INFINITY, f32::min);
+    let max = heights.clone().fold(f32::NEG_INFINITY, f32::max);
+    let range = if max > min { max - min } else { 1.0 };
+
+    let write = || -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(&path)?);
+        writeln!(writer, "P5")?;
+        writeln!(writer, "# heights from {} to {}", min, max)?;
+        writeln!(writer, "{} {}", samples.width, samples.depth)?;
+        writeln!(writer, "65535")?;
+        for height in heights {
+            let value = ((height - min) / range * 65535.0).round() as u16;
+            writer.write_all(&value.to_be_bytes())?;
+        }
+        writer.flush()
+    };
+    write().err()
+}
+
+/// Writes the terrain as Wavefront OBJ mesh with vertex normals.
+pub fn terrain_to_obj(
+    world: &impl World,
+    subdivisions: usize,
+    path: impl AsRef<Path>,
+) -> Option<Error> {
+    let samples = TerrainSamples::new(world, subdivisions);
+
+    let write = || -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(&path)?);
+        for pos in &samples.positions {
+            writeln!(writer, "v {} {} {}", pos.x, pos.y, pos.z)?;
+        }
+        for normal in &samples.normals {
+            writeln!(writer, "vn {} {} {}", normal.x, normal.y, normal.z)?;
+        }
+        for [a, b, c] in samples.triangles() {
+            let (a, b, c) = (a + 1, b + 1, c + 1);
+            writeln!(writer, "f {}//{} {}//{} {}//{}", a, a, b, b, c, c)?;
+        }
+        writer.flush()
+    };
+    write().err()
+}
+
+/// Writes the terrain as binary PLY mesh with vertex normals.
+pub fn terrain_to_ply(
+    world: &impl World,
+    subdivisions: usize,
+    path: impl AsRef<Path>,
+) -> Option<Error> {
+    let samples = TerrainSamples::new(world, subdivisions);
+    let triangles = samples.triangles();
+
+    let write = || -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(&path)?);
+        writeln!(writer, "ply")?;
+        writeln!(writer, "format binary_little_endian 1.0")?;
+        writeln!(writer, "element vertex {}", samples.positions.len())?;
+        for property in &["x", "y", "z", "nx", "ny", "nz"] {
+            writeln!(writer, "property float {}", property)?;
+        }
+        writeln!(writer, "element face {}", triangles.len())?;
+        writeln!(writer, "property list uchar uint vertex_indices")?;
+        writeln!(writer, "end_header")?;
+
+        for (pos, normal) in samples.positions.iter().zip(samples.normals.iter()) {
+            for value in &[pos.x, pos.y, pos.z, normal.x, normal.y, normal.z] {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+        for triangle in triangles {
+            writer.write_all(&[3])?;
+            for index in &triangle {
+                writer.write_all(&(*index as u32).to_le_bytes())?;
+            }
+        }
+        writer.flush()
+    };
+    write().err()
+}
+
+#[test]
+fn test_terrain_export() {
+    use crate::swarm::{
+        genome::{terrain::HeightmapConfig, CellOverflow},
+        world::ChunkedWorld,
+    };
+
+    let mut world = ChunkedWorld::new(
+        vec![],
+        (3, 2),
+        10.0,
+        (0.0, 0.0),
+        10.0,
+        CellOverflow::Clamp,
+        Default::default(),
+    );
+    let config = HeightmapConfig {
+        path: "ramp.csv".into(),
+        scale: 10.0,
+        offset: 0.0,
+        anchor: 1.0,
+    };
+    world.apply_heightmap(&Heightmap::from_csv("0, 1\n0, 1").unwrap(), &config);
+
+    let dir = std::env::temp_dir().join(format!("crustswarm_export_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    assert!(terrain_to_obj(&world, 2, dir.join("t.obj")).is_none());
+    let obj = fs::read_to_string(dir.join("t.obj")).unwrap();
+    assert_eq!(obj.lines().filter(|l| l.starts_with("v ")).count(), 5 * 3);
+    assert_eq!(obj.lines().filter(|l| l.starts_with("vn ")).count(), 5 * 3);
+    assert_eq!(
+        obj.lines().filter(|l| l.starts_with("f ")).count(),
+        2 * 4 * 2
+    );
+    assert!(obj.contains("v -10 0 0\n") && obj.contains("v 10 10 10\n"));
+
+    assert!(terrain_to_ply(&world, 1, dir.join("t.ply")).is_none());
+    let ply = fs::read(dir.join("t.ply")).unwrap();
+    let header_end = b"end_header\n";
+    let body = ply
+        .windows(header_end.len())
+        .position(|w| w == header_end)
+        .unwrap()
+        + 11;
+    assert_eq!(ply.len() - body, 3 * 2 * 6 * 4 + 2 * 2 * 13);
+
+    assert!(terrain_to_pgm(&world, 1, dir.join("t.pgm")).is_none());
+    let pgm_bytes = fs::read(dir.join("t.pgm")).unwrap();
+    assert!(pgm_bytes.starts_with(b"P5\n# heights from 0 to 10\n3 2\n65535\n"));
+    let pgm = Heightmap::from_pgm(&pgm_bytes).unwrap();
+    assert_eq!((pgm.sample(0.0, 0.0), pgm.sample(1.0, 1.0)), (0.0, 1.0));
+    assert!((pgm.sample(0.5, 0.0) - 0.5).abs() < 1e-4);
+
+    fs::remove_dir_all(dir).unwrap();
+}
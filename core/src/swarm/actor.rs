@@ -49,6 +49,52 @@ impl Into<SurroundingIndex> for Actor {
     }
 }
 
+/// Borrowed view of an agent or artifact living in a world.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ActorRef<'a> {
+    Agent(&'a Agent),
+    Artifact(&'a Artifact),
+}
+
+impl<'a> ActorRef<'a> {
+    pub fn get_id(&self) -> Uid {
+        match self {
+            ActorRef::Agent(agent) => agent.id,
+            ActorRef::Artifact(artifact) => artifact.id,
+        }
+    }
+    pub fn get_surrounding_index(&self) -> SurroundingIndex {
+        match self {
+            ActorRef::Agent(agent) => agent.species_index.into(),
+            ActorRef::Artifact(artifact) => artifact.artifact_index.into(),
+        }
+    }
+    pub fn get_position(&self) -> Vector3<f32> {
+        match self {
+            ActorRef::Agent(agent) => agent.position,
+            ActorRef::Artifact(artifact) => artifact.position,
+        }
+    }
+    pub fn has_energy(&self) -> bool {
+        match self {
+            ActorRef::Agent(agent) => agent.has_energy(),
+            _ => true,
+        }
+    }
+    pub fn cloned(&self) -> Actor {
+        match self {
+            ActorRef::Agent(agent) => Actor::Agent((*agent).clone()),
+            ActorRef::Artifact(artifact) => Actor::Artifact((*artifact).clone()),
+        }
+    }
+}
+
+impl<'a> From<ActorRef<'a>> for SurroundingIndex {
+    fn from(actor: ActorRef<'a>) -> SurroundingIndex {
+        actor.get_surrounding_index()
+    }
+}
+
 #[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct Agent {
     pub position: Vector3<f32>,
@@ -59,6 +105,9 @@ pub struct Agent {
     pub id: Uid,
     pub iteration: usize,
     pub last: Option<Uid>,
+    /// Acceleration in the last step, if the agent moved before.
+    #[serde(default)]
+    pub acceleration: Option<Vector3<f32>>,
 }
 
 impl fmt::Debug for Agent {
@@ -102,6 +151,7 @@ impl Agent {
             last: None,
             id: uid,
             iteration,
+            acceleration: None,
         }
     }
 
@@ -124,6 +174,7 @@ impl Agent {
             last: None,
             iteration,
             id: uid,
+            acceleration: None,
         })
     }
 
@@ -0,0 +1,192 @@
+use super::genome::terrain::{HydraulicErosion, Smoothing, TerrainErosion, ThermalErosion};
+
+/// Heights of a regular grid, stored x major.
+pub(crate) struct HeightGrid {
+    pub heights: Vec<f32>,
+    pub x_size: usize,
+    pub z_size: usize,
+    pub spacing: f32,
+}
+
+impl HeightGrid {
+    /// Runs all enabled passes of `erosion`.
+    pub fn erode(&mut self, erosion: &TerrainErosion) {
+        if let Some(smoothing) = &erosion.smoothing {
+            self.smooth(smoothing);
+        }
+        if let Some(thermal) = &erosion.thermal {
+            self.thermal(thermal);
+        }
+        if let Some(hydraulic) = &erosion.hydraulic {
+            self.hydraulic(hydraulic);
+        }
+    }
+
+    /// Indices of the direct neighbours along x and z.
+    fn neighbours(&self, index: usize) -> impl Iterator<Item = usize> {
+        let (x, z) = (index / self.z_size, index % self.z_size);
+        let (x_size, z_size) = (self.x_size, self.z_size);
+        IntoIterator::into_iter([
+            (x > 0).then(|| index - z_size),
+            (x + 1 < x_size).then(|| index + z_size),
+            (z > 0).then(|| index - 1),
+            (z + 1 < z_size).then(|| index + 1),
+        ])
+        .flatten()
+    }
+
+    fn smooth(&mut self, config: &Smoothing) {
+        for _ in 0..config.iterations {
+            let smoothed = (0..self.heights.len())
+                .map(|i| {
+                    let (sum, count) = self.neighbours(i).fold((0.0, 0), |(sum, count), n| {
+                        (sum + self.heights[n], count + 1)
+                    });
+                    let height = self.heights[i];
+                    if count == 0 {
+                        height
+                    } else {
+                        height + config.strength * (sum / count as f32 - height)
+                    }
+                })
+                .collect();
+            self.heights = smoothed;
+        }
+    }
+
+    fn thermal(&mut self, config: &ThermalErosion) {
+        let talus = config.talus * self.spacing;
+        for _ in 0..config.iterations {
+            let mut delta = vec![0.0; self.heights.len()];
+            for i in 0..self.heights.len() {
+                let drops: Vec<(usize, f32)> = self
+                    .neighbours(i)
+                    .map(|n| (n, self.heights[i] - self.heights[n]))
+                    .filter(|(_, drop)| *drop > talus)
+                    .collect();
+                let max_drop = drops.iter().map(|(_, drop)| *drop).fold(0.0, f32::max);
+                if max_drop <= talus {
+                    continue;
+                }
+
+                let total: f32 = drops.iter().map(|(_, drop)| drop).sum();
+                let moved = config.rate * (max_drop - talus) * 0.5;
+                delta[i] -= moved;
+                for (n, drop) in drops {
+                    delta[n] += moved * drop / total;
+                }
+            }
+            self.heights
+                .iter_mut()
+                .zip(delta)
+                .for_each(|(h, d)| *h += d);
+        }
+    }
+
+    fn hydraulic(&mut self, config: &HydraulicErosion) {
+        let len = self.heights.len();
+        let mut water = vec![0.0; len];
+        let mut sediment = vec![0.0; len];
+
+        for _ in 0..config.iterations {
+            for i in 0..len {
+                water[i] += config.rain;
+                let dissolved = config.solubility * water[i];
+                self.heights[i] -= dissolved;
+                sediment[i] += dissolved;
+            }
+
+            let mut water_delta = vec![0.0; len];
+            let mut sediment_delta = vec![0.0; len];
+            for i in 0..len {
+                let level = self.heights[i] + water[i];
+                let drops: Vec<(usize, f32)> = self
+                    .neighbours(i)
+                    .map(|n| (n, level - self.heights[n] - water[n]))
+                    .filter(|(_, drop)| *drop > 0.0)
+                    .collect();
+                let total: f32 = drops.iter().map(|(_, drop)| drop).sum();
+                let max_drop = drops.iter().map(|(_, drop)| *drop).fold(0.0, f32::max);
+                if total <= 0.0 || water[i] <= 0.0 {
+                    continue;
+                }
+
+                let moved = water[i].min(max_drop * 0.5);
+                let carried = sediment[i] * moved / water[i];
+                water_delta[i] -= moved;
+                sediment_delta[i] -= carried;
+                for (n, drop) in drops {
+                    water_delta[n] += moved * drop / total;
+                    sediment_delta[n] += carried * drop / total;
+                }
+            }
+
+            for i in 0..len {
+                water[i] = (water[i] + water_delta[i]) * (1.0 - config.evaporation);
+                sediment[i] += sediment_delta[i];
+                let excess = sediment[i] - config.capacity * water[i];
+                if excess > 0.0 {
+                    self.heights[i] += excess;
+                    sediment[i] -= excess;
+                }
+            }
+        }
+
+        self.heights
+            .iter_mut()
+            .zip(sediment)
+            .for_each(|(h, s)| *h += s);
+    }
+}
+
+#[test]
+fn test_erosion_flattens_spike_and_keeps_volume() {
+    use super::genome::terrain::*;
+
+    let mut heights = vec![0.0; 49];
+    heights[24] = 10.0;
+    let volume = |grid: &HeightGrid| grid.heights.iter().sum::<f32>();
+
+    let passes = [
+        TerrainErosion {
+            smoothing: Some(Smoothing {
+                strength: 0.5,
+                iterations: 1,
+            }),
+            ..Default::default()
+        },
+        TerrainErosion {
+            thermal: Some(ThermalErosion {
+                talus: 0.5,
+                rate: 0.5,
+                iterations: 5,
+            }),
+            ..Default::default()
+        },
+        TerrainErosion {
+            hydraulic: Some(HydraulicErosion {
+                rain: 0.1,
+                solubility: 0.1,
+                evaporation: 0.5,
+                capacity: 0.1,
+                iterations: 10,
+            }),
+            ..Default::default()
+        },
+    ];
+
+    for erosion in passes.iter() {
+        let mut grid = HeightGrid {
+            heights: heights.clone(),
+            x_size: 7,
+            z_size: 7,
+            spacing: 1.0,
+        };
+        grid.erode(erosion);
+        assert!(grid.heights[24] < 10.0, "{:?}", erosion);
+        assert!(grid.heights[23] > 0.0, "{:?}", erosion);
+        if erosion.smoothing.is_none() {
+            assert!((volume(&grid) - 10.0).abs() < 1e-3, "{:?}", erosion);
+        }
+    }
+}
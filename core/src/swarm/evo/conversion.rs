@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::convert::TryFrom;
 
 use cgmath::Vector3;
 
@@ -111,6 +112,8 @@ impl From<&OIDESwarmGenome> for SwarmGenome {
                             range: oide_rule.range.get_value(),
                             weight: oide_rule.weight.get_value(),
                             persist: oide_rule.persist.clone().into(),
+                            conditions: (*oide_rule.conditions).clone(),
+                            guards: (*oide_rule.guards).clone(),
                             replacement: Replacement::Simple(
                                 FlattenableIntoSurroundingVec::flatten_into_surrounding_vec(
                                     &oide_rule.replacement.get_indices(),
@@ -147,20 +150,17 @@ impl From<&OIDESwarmGenome> for SwarmGenome {
 
                 Species {
                     index: SpeciesIndex(*oide_species.index),
-                    separation: oide_species.separation.get_value(),
-                    alignment: oide_species.alignment.get_value(),
-                    cohesion: oide_species.cohesion.get_value(),
-                    randomness: oide_species.randomness.get_value(),
-                    center: oide_species.center.get_value(),
-                    floor: oide_species.floor.get_value(),
+                    urges: oide_species
+                        .urges
+                        .0
+                        .iter()
+                        .map(BoundedFactor::get_value)
+                        .collect(),
                     bias: Vector3::new(
                         oide_species.bias.0.get_value(),
                         oide_species.bias.1.get_value(),
                         oide_species.bias.2.get_value(),
                     ),
-                    gradient: oide_species.gradient.get_value(),
-                    normal: oide_species.normal.get_value(),
-                    slope: oide_species.slope.get_value(),
                     normal_speed: oide_species.normal_speed.get_value()
                         * oide_species.max_speed.get_value(),
                     max_speed: oide_species.max_speed.get_value(),
@@ -178,8 +178,12 @@ impl From<&OIDESwarmGenome> for SwarmGenome {
                     noclip: oide_species.noclip.is_active(),
                     energy,
                     hand_down_seed: oide_species.hand_down_seed.clone().into(),
+                    fields: (*oide_species.fields).clone(),
                     rules,
                     color_index: *oide_species.color_index,
+                    boundary: *oide_species.boundary,
+                    susceptibility: *oide_species.susceptibility,
+                    strategy: *oide_species.strategy,
                 }
             })
             .collect();
@@ -199,14 +203,28 @@ impl From<&OIDESwarmGenome> for SwarmGenome {
                 oide_genome.terrain_influences.0.into_f32_vec(),
                 oide_genome.terrain_influences.1.into_f32_vec(),
             ),
+            terrain_behaviors: (*oide_genome.terrain_behaviors).clone(),
             terrain_size: *oide_genome.terrain_size,
+            terrain_z_size: *oide_genome.terrain_z_size,
             terrain_spacing: *oide_genome.terrain_spacing,
+            terrain_origin: *oide_genome.terrain_origin,
+            terrain_update: *oide_genome.terrain_update,
+            terrain_heightmap: (*oide_genome.terrain_heightmap).clone(),
+            terrain_erosion: *oide_genome.terrain_erosion,
+            grid: (*oide_genome.grid).clone(),
+            bounds: *oide_genome.bounds,
+            obstacles: (*oide_genome.obstacles).clone(),
+            fields: (*oide_genome.fields).clone(),
+            forces: (*oide_genome.forces).clone(),
+            integration: *oide_genome.integration,
         }
     }
 }
 
-impl From<&SwarmGenome> for OIDESwarmGenome {
-    fn from(genome: &SwarmGenome) -> Self {
+/// Fails for replacements the OIDE genome can not represent yet, instead of dropping them.
+impl TryFrom<&SwarmGenome> for OIDESwarmGenome {
+    type Error = String;
+    fn try_from(genome: &SwarmGenome) -> Result<Self, Self::Error> {
         let species_count = genome.species_map.iter().count();
         let artifact_count = genome.artifact_map.iter().count();
         //dbg!(&species_count);
@@ -255,18 +273,26 @@ impl From<&SwarmGenome> for OIDESwarmGenome {
                     .collect();
                 //dbg!(&artifact_influences);
 
-                fn conv_rep(rep: &Replacement) -> Vec<SurroundingIndex> {
+                fn conv_rep(rep: &Replacement) -> Result<Vec<SurroundingIndex>, String> {
                     match rep {
-                        Replacement::None => vec![],
+                        Replacement::None => Ok(vec![]),
                         Replacement::Simple(a) => {
                             let foo = a.iter().map(|b| b.to_owned()).collect();
-                            foo
+                            Ok(foo)
                         }
                         Replacement::Multi(a) => {
-                            let foo = a.iter().flat_map(|b| conv_rep(b)).collect();
-                            foo
+                            let foo = a.iter().map(|b| conv_rep(b)).collect::<Result<Vec<_>, _>>();
+                            foo.map(|reps| reps.concat())
                         }
-                        Replacement::Spread(_, _, _) => vec![],
+                        Replacement::Spread(_, _, _) => Ok(vec![]),
+                        Replacement::Pattern(_, _, pattern) => Err(format!(
+                            "Pattern replacements ({:?}) can not be converted to OIDE",
+                            pattern
+                        )),
+                        Replacement::Transformed(_, transform) => Err(format!(
+                            "Transformed replacements ({:?}) can not be converted to OIDE",
+                            transform
+                        )),
                     }
                 }
 
@@ -296,7 +322,7 @@ impl From<&SwarmGenome> for OIDESwarmGenome {
                             ZeroEnergy::Replace(v1, reps) => {
                                 let mut distribution: Vec<f32> =
                                     vec![0.0; artifact_count + species_count];
-                                conv_rep(&reps)
+                                conv_rep(&reps)?
                                     .iter()
                                     .map(|i| to_usize(i.clone()))
                                     .for_each(|idx| distribution[idx] = distribution[idx] + 1.0);
@@ -360,20 +386,22 @@ impl From<&SwarmGenome> for OIDESwarmGenome {
                             let idx = to_usize(*surr_idx);
                             context[idx] = context[idx] + 1.0;
                         });
-                        conv_rep(&rule.replacement).iter().for_each(|surr_idx| {
+                        conv_rep(&rule.replacement)?.iter().for_each(|surr_idx| {
                             let idx = to_usize(*surr_idx);
                             replacement[idx] = replacement[idx] + 1.0;
                         });
 
-                        OIDEContextRule {
+                        Ok(OIDEContextRule {
                             context: context.into(),
                             range: BoundedFactor::new_from_f32(rule.range),
                             weight: BoundedFactor::new_from_f32(rule.weight),
                             persist: rule.persist.into(),
                             replacement: replacement.into(),
-                        }
+                            conditions: rule.conditions.clone().into(),
+                            guards: rule.guards.clone().into(),
+                        })
                     })
-                    .collect();
+                    .collect::<Result<Vec<_>, String>>()?;
 
                 rules.resize(
                     rule_count,
@@ -398,22 +426,20 @@ impl From<&SwarmGenome> for OIDESwarmGenome {
                         .into(),
                 };
 
-                OIDESpecies {
+                Ok(OIDESpecies {
                     index: to_usize(species.index.into()).into(),
-                    separation: BoundedFactor::new_from_f32(species.separation),
-                    alignment: BoundedFactor::new_from_f32(species.alignment),
-                    cohesion: BoundedFactor::new_from_f32(species.cohesion),
-                    randomness: BoundedFactor::new_from_f32(species.randomness),
-                    center: BoundedFactor::new_from_f32(species.center),
-                    floor: BoundedFactor::new_from_f32(species.floor),
+                    urges: OIDEUrges(
+                        species
+                            .urges
+                            .iter()
+                            .map(|weight| BoundedFactor::new_from_f32(*weight))
+                            .collect(),
+                    ),
                     bias: (
                         BoundedFactor::new_from_f32(species.bias.x),
                         BoundedFactor::new_from_f32(species.bias.y),
                         BoundedFactor::new_from_f32(species.bias.z),
                     ),
-                    gradient: BoundedFactor::new_from_f32(species.gradient),
-                    normal: BoundedFactor::new_from_f32(species.normal),
-                    slope: BoundedFactor::new_from_f32(species.slope),
                     normal_speed: BoundedFactor::new_from_f32(
                         species.normal_speed / species.max_speed,
                     ),
@@ -432,11 +458,15 @@ impl From<&SwarmGenome> for OIDESwarmGenome {
                     noclip: species.noclip.into(),
                     energy,
                     hand_down_seed: species.hand_down_seed.into(),
+                    fields: species.fields.clone().into(),
                     rules: ruleset,
                     color_index: species.color_index.into(),
-                }
+                    boundary: species.boundary.into(),
+                    susceptibility: species.susceptibility.into(),
+                    strategy: species.strategy.into(),
+                })
             })
-            .collect();
+            .collect::<Result<Vec<_>, String>>()?;
 
         let terrain_species_influences = genome
             .terrain_influences
@@ -453,7 +483,7 @@ impl From<&SwarmGenome> for OIDESwarmGenome {
             .collect::<BoundedFactorVec>()
             .into();
 
-        return OIDESwarmGenome {
+        Ok(OIDESwarmGenome {
             species_count: species_count.into(),
             artifact_count: artifact_count.into(),
             rule_count: rule_count.into(),
@@ -466,8 +496,37 @@ impl From<&SwarmGenome> for OIDESwarmGenome {
             start_dist: genome.start_dist.clone().into(),
             strategy: genome.strategy.clone().into(),
             terrain_influences: (terrain_species_influences, terrain_artifact_influences),
+            terrain_behaviors: genome.terrain_behaviors.clone().into(),
             terrain_size: genome.terrain_size.into(),
+            terrain_z_size: genome.terrain_z_size.into(),
             terrain_spacing: genome.terrain_spacing.into(),
-        };
+            terrain_origin: genome.terrain_origin.into(),
+            terrain_update: genome.terrain_update.into(),
+            terrain_heightmap: genome.terrain_heightmap.clone().into(),
+            terrain_erosion: genome.terrain_erosion.into(),
+            grid: genome.grid.clone().into(),
+            bounds: genome.bounds.into(),
+            obstacles: genome.obstacles.clone().into(),
+            fields: genome.fields.clone().into(),
+            forces: genome.forces.clone().into(),
+            integration: genome.integration.into(),
+        })
     }
 }
+
+#[test]
+fn test_unconvertible_replacements_are_rejected() {
+    use super::super::genome::replacement::{OffspringTransform, SpreadPattern};
+
+    let mut genome = crate::utils::sample_genome("context_config.json");
+    assert!(OIDESwarmGenome::try_from(&genome).is_ok());
+
+    let rule = &mut genome.species_map[0].rules[0];
+    let simple = rule.replacement.clone();
+    rule.replacement = Replacement::Transformed(Box::new(simple), OffspringTransform::default());
+    assert!(OIDESwarmGenome::try_from(&genome).is_err());
+
+    let pattern = Replacement::Pattern(SpeciesIndex(0), 3, SpreadPattern::Sphere);
+    genome.species_map[0].rules[0].replacement = Replacement::Multi(vec![pattern]);
+    assert!(OIDESwarmGenome::try_from(&genome).is_err());
+}
@@ -5,9 +5,18 @@ use rand::prelude::IteratorRandom;
 use serde::{Deserialize, Serialize};
 
 use super::super::genome::{
-    replacement::ApplicationStrategy, Distribution, SpeciesIndex, SurroundingIndex,
+    bounds::{BoundaryPolicy, WorldBounds},
+    field::{FieldConfig, FieldInteraction},
+    force::ForceField,
+    integration::Integration,
+    obstacle::Obstacle,
+    replacement::{ApplicationStrategy, ContextCondition, StateGuard},
+    terrain::{InfluenceBehavior, TerrainErosion, TerrainHeightmap, TerrainUpdate},
+    Distribution, GridConfig, SpeciesIndex, SurroundingIndex,
 };
 
+use super::super::urge::{deserialize_named, serialize_named, Urge, URGES};
+
 use derive_diff::*;
 use r_oide::prelude::*;
 
@@ -39,23 +48,40 @@ pub struct OIDESwarmGenome {
     pub start_dist: Fixed<Distribution>,
     pub strategy: Fixed<ApplicationStrategy>,
     pub terrain_influences: (Fixed<BoundedFactorVec>, Fixed<BoundedFactorVec>),
+    #[serde(default)]
+    pub terrain_behaviors: Fixed<(Vec<InfluenceBehavior>, Vec<InfluenceBehavior>)>,
     pub terrain_size: Fixed<usize>,
+    #[serde(default)]
+    pub terrain_z_size: Fixed<Option<usize>>,
     pub terrain_spacing: Fixed<f32>,
+    #[serde(default)]
+    pub terrain_origin: Fixed<(f32, f32)>,
+    #[serde(default)]
+    pub terrain_update: Fixed<TerrainUpdate>,
+    #[serde(default)]
+    pub terrain_heightmap: Fixed<Option<TerrainHeightmap>>,
+    #[serde(default)]
+    pub terrain_erosion: Fixed<TerrainErosion>,
+    #[serde(default)]
+    pub grid: Fixed<GridConfig>,
+    #[serde(default)]
+    pub bounds: Fixed<Option<WorldBounds>>,
+    #[serde(default)]
+    pub obstacles: Fixed<Vec<Obstacle>>,
+    #[serde(default)]
+    pub fields: Fixed<Vec<FieldConfig>>,
+    #[serde(default)]
+    pub forces: Fixed<Vec<ForceField>>,
+    #[serde(default)]
+    pub integration: Fixed<Integration>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Hash, PartialEq, AllOIDETraits)]
 pub struct OIDESpecies {
     pub index: Fixed<usize>,
-    pub separation: BoundedFactor,
-    pub alignment: BoundedFactor,
-    pub cohesion: BoundedFactor,
-    pub randomness: BoundedFactor,
-    pub center: BoundedFactor,
-    pub floor: BoundedFactor,
+    #[serde(flatten)]
+    pub urges: OIDEUrges,
     pub bias: (BoundedFactor, BoundedFactor, BoundedFactor),
-    pub gradient: BoundedFactor,
-    pub normal: BoundedFactor,
-    pub slope: BoundedFactor,
     pub normal_speed: BoundedFactor,
     pub max_speed: BoundedFactor,
     pub max_acceleration: BoundedFactor,
@@ -68,8 +94,48 @@ pub struct OIDESpecies {
     pub noclip: FloatyBool,
     pub energy: OIDEEnergy,
     pub hand_down_seed: FloatyBool,
+    #[serde(default)]
+    pub fields: Fixed<Vec<FieldInteraction>>,
     pub rules: OIDERuleSet,
     pub color_index: Fixed<usize>,
+    #[serde(default)]
+    pub boundary: Fixed<BoundaryPolicy>,
+    #[serde(default = "full_susceptibility")]
+    pub susceptibility: Fixed<f32>,
+    #[serde(default)]
+    pub strategy: Fixed<Option<ApplicationStrategy>>,
+}
+
+fn full_susceptibility() -> Fixed<f32> {
+    1.0.into()
+}
+
+/// Weights of the urges in `urge::URGES`, stored by their names.
+#[derive(Debug, Serialize, Deserialize, Clone, Hash, PartialEq, AllOIDETraits)]
+#[serde(transparent)]
+pub struct OIDEUrges(
+    #[serde(
+        serialize_with = "serialize_oide_urges",
+        deserialize_with = "deserialize_oide_urges"
+    )]
+    pub Vec<BoundedFactor>,
+);
+
+fn missing_oide_urge(urge: &dyn Urge) -> BoundedFactor {
+    BoundedFactor::new_from_f32(urge.default_weight())
+}
+
+fn serialize_oide_urges<S: serde::Serializer>(
+    urges: &[BoundedFactor],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serialize_named(urges, serializer, missing_oide_urge)
+}
+
+fn deserialize_oide_urges<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<BoundedFactor>, D::Error> {
+    deserialize_named(deserializer, missing_oide_urge)
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Hash, Default, PartialEq, AllOIDETraits)]
@@ -113,6 +179,10 @@ pub struct OIDEContextRule {
     pub weight: BoundedFactor,
     pub persist: FloatyBool,
     pub replacement: IndexMultiset,
+    #[serde(default)]
+    pub conditions: Fixed<Vec<ContextCondition>>,
+    #[serde(default)]
+    pub guards: Fixed<Vec<StateGuard>>,
 }
 
 impl OIDECrossover for OIDESwarmGenome {
@@ -142,10 +212,38 @@ impl OIDECrossover for OIDESwarmGenome {
                 rng,
                 rate,
             ),
+            terrain_behaviors: self.terrain_behaviors.crossover(
+                &other.terrain_behaviors,
+                rng,
+                rate,
+            ),
             terrain_size: self.terrain_size.crossover(&other.terrain_size, rng, rate),
+            terrain_z_size: self
+                .terrain_z_size
+                .crossover(&other.terrain_z_size, rng, rate),
             terrain_spacing: self
                 .terrain_spacing
                 .crossover(&other.terrain_spacing, rng, rate),
+            terrain_origin: self
+                .terrain_origin
+                .crossover(&other.terrain_origin, rng, rate),
+            terrain_update: self
+                .terrain_update
+                .crossover(&other.terrain_update, rng, rate),
+            terrain_heightmap: self.terrain_heightmap.crossover(
+                &other.terrain_heightmap,
+                rng,
+                rate,
+            ),
+            terrain_erosion: self
+                .terrain_erosion
+                .crossover(&other.terrain_erosion, rng, rate),
+            grid: self.grid.crossover(&other.grid, rng, rate),
+            bounds: self.bounds.crossover(&other.bounds, rng, rate),
+            obstacles: self.obstacles.crossover(&other.obstacles, rng, rate),
+            fields: self.fields.crossover(&other.fields, rng, rate),
+            forces: self.forces.crossover(&other.forces, rng, rate),
+            integration: self.integration.crossover(&other.integration, rng, rate),
         }
     }
 }
@@ -167,6 +265,8 @@ impl OIDEOpposite for OIDEContextRule {
                 Some(ref m) => Some(&m.persist),
                 None => None,
             }),
+            conditions: self.conditions.clone(),
+            guards: self.guards.clone(),
         }
     }
 }
@@ -191,14 +291,27 @@ impl OIDESwarmGenome {
             strategy: ApplicationStrategy {
                 every: 2,
                 offset: 1,
+                probability: 1.0,
             }
             .into(),
             terrain_influences: (
                 BoundedFactorVec::new(0.0, 5.0, spec_count).into(),
                 BoundedFactorVec::new(0.0, 5.0, art_count).into(),
             ),
+            terrain_behaviors: (vec![], vec![]).into(),
             terrain_size: 40.into(),
+            terrain_z_size: None.into(),
             terrain_spacing: 6.0.into(),
+            terrain_origin: (0.0, 0.0).into(),
+            terrain_update: TerrainUpdate::default().into(),
+            terrain_heightmap: None.into(),
+            terrain_erosion: TerrainErosion::default().into(),
+            grid: GridConfig::default().into(),
+            bounds: None.into(),
+            obstacles: vec![].into(),
+            fields: vec![].into(),
+            forces: vec![].into(),
+            integration: Integration::default().into(),
         }
     }
 
@@ -237,20 +350,17 @@ impl OIDESpecies {
     ) -> OIDESpecies {
         OIDESpecies {
             index: index.into(),
-            separation: BoundedFactor::new_with_bounds(0.0, 2.0, 0.0),
-            alignment: BoundedFactor::new_with_bounds(0.0, 2.0, 0.0),
-            cohesion: BoundedFactor::new_with_bounds(0.0, 2.0, 0.0),
-            randomness: BoundedFactor::new_with_bounds(0.0, 1.0, 0.0),
-            center: BoundedFactor::new_with_bounds(0.0, 0.1, 0.0),
-            floor: BoundedFactor::new_with_bounds(0.0, 0.1, 0.0),
+            urges: OIDEUrges::new_with_bounds(|urge| match urge {
+                "separation" | "alignment" | "cohesion" => 2.0,
+                "center" | "floor" => 0.1,
+                "slope" => 0.5,
+                _ => 1.0,
+            }),
             bias: (
                 BoundedFactor::new_with_bounds(-0.5, 0.5, 0.0),
                 BoundedFactor::new_with_bounds(-0.5, 0.5, 0.0),
                 BoundedFactor::new_with_bounds(-0.5, 0.5, 0.0),
             ),
-            gradient: BoundedFactor::new_with_bounds(0.0, 1.0, 0.0),
-            normal: BoundedFactor::new_with_bounds(0.0, 1.0, 0.0),
-            slope: BoundedFactor::new_with_bounds(0.0, 0.5, 0.0),
             normal_speed: BoundedFactor::new_with_bounds(0.0, 1.0, 0.5),
             max_speed: BoundedFactor::new_with_bounds(0.0, 3.0, 1.0),
             max_acceleration: BoundedFactor::new_with_bounds(0.0, 3.0, 1.0),
@@ -270,8 +380,12 @@ impl OIDESpecies {
             noclip: false.into(),
             energy: OIDEEnergy::new_with_size(species_count + artifact_count),
             hand_down_seed: false.into(),
+            fields: vec![].into(),
             rules: OIDERuleSet::new_with_size(species_count + artifact_count, rule_count),
             color_index: index.into(),
+            boundary: BoundaryPolicy::default().into(),
+            susceptibility: 1.0.into(),
+            strategy: None.into(),
         }
     }
     pub fn new_with_size(
@@ -282,20 +396,15 @@ impl OIDESpecies {
     ) -> OIDESpecies {
         OIDESpecies {
             index: index.into(),
-            separation: BoundedFactor::new_with_bounds(0.0, 2.0, 0.0),
-            alignment: BoundedFactor::new_with_bounds(0.0, 2.0, 0.0),
-            cohesion: BoundedFactor::new_with_bounds(0.0, 2.0, 0.0),
-            randomness: BoundedFactor::new_with_bounds(0.0, 1.0, 0.0),
-            center: BoundedFactor::new_with_bounds(0.0, 1.0, 0.0),
-            floor: BoundedFactor::new_with_bounds(0.0, 1.0, 0.0),
+            urges: OIDEUrges::new_with_bounds(|urge| match urge {
+                "separation" | "alignment" | "cohesion" => 2.0,
+                _ => 1.0,
+            }),
             bias: (
                 BoundedFactor::new_with_bounds(-1.0, 1.0, 0.0),
                 BoundedFactor::new_with_bounds(-1.0, 1.0, 0.0),
                 BoundedFactor::new_with_bounds(-1.0, 1.0, 0.0),
             ),
-            gradient: BoundedFactor::new_with_bounds(0.0, 1.0, 0.0),
-            normal: BoundedFactor::new_with_bounds(0.0, 1.0, 0.0),
-            slope: BoundedFactor::new_with_bounds(0.0, 1.0, 0.0),
             normal_speed: BoundedFactor::new_with_bounds(0.0, 1.0, 0.5),
             max_speed: BoundedFactor::new_with_bounds(0.0, 3.0, 1.0),
             max_acceleration: BoundedFactor::new_with_bounds(0.0, 3.0, 1.0),
@@ -315,12 +424,32 @@ impl OIDESpecies {
             noclip: false.into(),
             energy: OIDEEnergy::new_with_size(species_count + artifact_count),
             hand_down_seed: false.into(),
+            fields: vec![].into(),
             rules: OIDERuleSet::new_with_size(species_count + artifact_count, rule_count),
             color_index: index.into(),
+            boundary: BoundaryPolicy::default().into(),
+            susceptibility: 1.0.into(),
+            strategy: None.into(),
         }
     }
 }
 
+impl OIDEUrges {
+    /// Urges at their default weight, which may evolve up to the bound given for their name.
+    pub fn new_with_bounds(upper_bound: impl Fn(&str) -> f32) -> OIDEUrges {
+        OIDEUrges(
+            URGES
+                .iter()
+                .map(|urge| {
+                    let upper = upper_bound(urge.name());
+                    let weight = urge.default_weight().max(0.0).min(upper);
+                    BoundedFactor::new_with_bounds(0.0, upper, weight)
+                })
+                .collect(),
+        )
+    }
+}
+
 impl OIDEEnergy {
     pub fn new_with_size(index_count: usize) -> OIDEEnergy {
         OIDEEnergy {
@@ -364,6 +493,23 @@ impl OIDEContextRule {
             weight: BoundedFactor::new_with_bounds(0.0, 100.0, 0.0), // TODO: change to 0.01
             persist: true.into(),
             replacement: IndexMultiset::new_with_size(index_count),
+            conditions: vec![].into(),
+            guards: vec![].into(),
         }
     }
 }
+
+#[test]
+fn test_new_urges_start_at_their_default_weight() {
+    let urges = OIDEUrges::new_with_bounds(|urge| if urge == "trail" { 2.0 } else { 0.5 });
+    let index = crate::swarm::urge::urge_index("trail").unwrap();
+    assert_eq!(urges.0[index].get_value(), 1.0);
+    assert!(urges
+        .0
+        .iter()
+        .enumerate()
+        .all(|(i, weight)| i == index || weight.get_value() == 0.0));
+
+    let clamped = OIDEUrges::new_with_bounds(|_| 0.5);
+    assert_eq!(clamped.0[index].get_value(), 0.5);
+}
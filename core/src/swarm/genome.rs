@@ -1,16 +1,32 @@
+pub mod bounds;
 pub mod dummies;
 pub mod energy;
+pub mod field;
+pub mod force;
+pub mod integration;
+pub mod obstacle;
 pub mod replacement;
+pub mod terrain;
 
 use crate::swarm::actor::{Agent, Artifact};
 use cgmath::Vector3;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::convert::TryFrom;
+use std::path::PathBuf;
 
+use self::bounds::{BoundaryPolicy, WorldBounds};
 use self::dummies::*;
+use self::field::{FieldConfig, FieldInteraction};
+use self::force::ForceField;
+use self::integration::Integration;
+use self::obstacle::{Obstacle, ObstacleConfig};
 use self::replacement::*;
+use self::terrain::{
+    Heightmap, InfluenceBehavior, TerrainErosion, TerrainHeightmap, TerrainUpdate,
+};
 
+use crate::swarm::urge::UrgeWeights;
 use crate::utils::{Uid, UidGen};
 
 #[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
@@ -119,8 +135,36 @@ pub struct SwarmGenome {
     pub start_dist: Distribution,
     pub strategy: ApplicationStrategy,
     pub terrain_influences: (Vec<f32>, Vec<f32>),
+    /// Indexed like `terrain_influences`. Missing entries pull with the default falloff.
+    #[serde(default)]
+    pub terrain_behaviors: (Vec<InfluenceBehavior>, Vec<InfluenceBehavior>),
     pub terrain_size: usize,
+    /// Buoys along z. Falls back to `terrain_size` if unset.
+    #[serde(default)]
+    pub terrain_z_size: Option<usize>,
     pub terrain_spacing: f32,
+    #[serde(default)]
+    pub terrain_origin: (f32, f32),
+    #[serde(default)]
+    pub terrain_update: TerrainUpdate,
+    #[serde(default)]
+    pub terrain_heightmap: Option<TerrainHeightmap>,
+    #[serde(default)]
+    pub terrain_erosion: TerrainErosion,
+    #[serde(default)]
+    pub grid: GridConfig,
+    /// Box the agents are kept in, unbounded if unset.
+    #[serde(default)]
+    pub bounds: Option<WorldBounds>,
+    #[serde(default)]
+    pub obstacles: Vec<Obstacle>,
+    #[serde(default)]
+    pub fields: Vec<FieldConfig>,
+    /// Global forces like wind or gravity.
+    #[serde(default)]
+    pub forces: Vec<ForceField>,
+    #[serde(default)]
+    pub integration: Integration,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
@@ -128,19 +172,34 @@ pub struct ArtifactType {
     pub color_index: usize,
 }
 
+/// Layout of the spatial hash used to look up neighboring actors.
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+pub struct GridConfig {
+    /// Edge length of a cubic cell. Falls back to the terrain spacing if unset.
+    #[serde(default)]
+    pub cell_size: Option<f32>,
+    #[serde(default)]
+    pub overflow: CellOverflow,
+}
+
+/// Handling of actors whose position can not be mapped onto a cell, because it is not finite
+/// or too far away from the origin.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum CellOverflow {
+    /// Keep the actor in the outermost cell in its direction.
+    #[default]
+    Clamp,
+    /// Remove the actor from the world.
+    Remove,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Species {
     pub index: SpeciesIndex,
-    pub separation: Factor,
-    pub alignment: Factor,
-    pub cohesion: Factor,
-    pub randomness: Factor,
-    pub center: Factor,
-    pub floor: Factor,
+    /// Weights of the urges in `urge::URGES`, stored by their names.
+    #[serde(flatten)]
+    pub urges: UrgeWeights,
     pub bias: Vector3<Factor>,
-    pub gradient: Factor,
-    pub normal: Factor,
-    pub slope: Factor,
     pub normal_speed: Factor,
     pub max_speed: Factor,
     pub max_acceleration: Factor,
@@ -153,8 +212,18 @@ pub struct Species {
     pub noclip: bool,
     pub energy: energy::Energy,
     pub hand_down_seed: bool,
+    #[serde(default)]
+    pub fields: Vec<FieldInteraction>,
     pub rules: Vec<ContextRule>,
     pub color_index: usize,
+    #[serde(default)]
+    pub boundary: BoundaryPolicy,
+    /// Scales the global forces acting on the agents of this species.
+    #[serde(default = "crate::utils::one")]
+    pub susceptibility: Factor,
+    /// Overrides the strategy of the genome for this species.
+    #[serde(default)]
+    pub strategy: Option<ApplicationStrategy>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
@@ -174,10 +243,40 @@ impl SwarmGenome {
         &self.species_map[agent.species_index.0]
     }
 
+    /// Strategy deciding when the agents of a species are replaced.
+    pub fn get_strategy(&self, species_index: &SpeciesIndex) -> &ApplicationStrategy {
+        self.species_map[species_index.0]
+            .strategy
+            .as_ref()
+            .unwrap_or(&self.strategy)
+    }
+
+    /// Whether the agents of any species are replaced in this step.
+    pub fn should_replace(&self) -> bool {
+        self.strategy.should_replace()
+            || self
+                .species_map
+                .iter()
+                .filter_map(|species| species.strategy.as_ref())
+                .any(ApplicationStrategy::should_replace)
+    }
+
     pub fn get_artifact_type(&self, artifact: &Artifact) -> &ArtifactType {
         &self.artifact_map[artifact.artifact_index.0]
     }
 
+    pub fn get_cell_size(&self) -> f32 {
+        self.grid.cell_size.unwrap_or(self.terrain_spacing)
+    }
+
+    /// Buoys along x and z.
+    pub fn get_terrain_size(&self) -> (usize, usize) {
+        (
+            self.terrain_size,
+            self.terrain_z_size.unwrap_or(self.terrain_size),
+        )
+    }
+
     pub fn get_start(
         &self,
         rnd: &mut impl rand::Rng,
@@ -188,6 +287,11 @@ impl SwarmGenome {
 
     pub fn tick(&mut self) {
         self.strategy.tick();
+        for species in self.species_map.iter_mut() {
+            if let Some(strategy) = &mut species.strategy {
+                strategy.tick();
+            }
+        }
     }
 
     fn distribute(
@@ -290,9 +394,27 @@ impl SwarmGenome {
     }
 }
 
+/// The contents of the files a genome refers to, loaded by `io::genome_files`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GenomeFiles {
+    pub heightmap: Option<Heightmap>,
+    /// Texts of the `.obj` files of mesh obstacles, keyed by their path.
+    pub meshes: HashMap<PathBuf, String>,
+}
+
+/// Converts genomes without heightmap or mesh obstacles, which need their files loaded.
 impl TryFrom<DummySwarmGenome> for SwarmGenome {
     type Error = String;
     fn try_from(dummy: DummySwarmGenome) -> Result<SwarmGenome, Self::Error> {
+        SwarmGenome::try_from((dummy, GenomeFiles::default()))
+    }
+}
+
+impl TryFrom<(DummySwarmGenome, GenomeFiles)> for SwarmGenome {
+    type Error = String;
+    fn try_from(
+        (dummy, mut files): (DummySwarmGenome, GenomeFiles),
+    ) -> Result<SwarmGenome, Self::Error> {
         type M = HashMap<String, usize>;
         fn convert_identifier(
             specs: &M,
@@ -308,6 +430,49 @@ impl TryFrom<DummySwarmGenome> for SwarmGenome {
             }
         }
 
+        fn convert_condition(
+            specs: &M,
+            arts: &M,
+            dummy_condition: &DummyContextCondition,
+        ) -> Result<ContextCondition, String> {
+            let convert_all = |conditions: &[DummyContextCondition]| {
+                conditions
+                    .iter()
+                    .map(|condition| convert_condition(specs, arts, condition))
+                    .collect::<Result<Vec<_>, _>>()
+            };
+            match dummy_condition {
+                DummyContextCondition::Absent { of, range } => Ok(ContextCondition::AtMost {
+                    of: convert_identifier(specs, arts, of.0.to_owned())?,
+                    count: 0,
+                    range: *range,
+                }),
+                DummyContextCondition::AtLeast { of, count, range } => {
+                    Ok(ContextCondition::AtLeast {
+                        of: convert_identifier(specs, arts, of.0.to_owned())?,
+                        count: *count,
+                        range: *range,
+                    })
+                }
+                DummyContextCondition::AtMost { of, count, range } => {
+                    Ok(ContextCondition::AtMost {
+                        of: convert_identifier(specs, arts, of.0.to_owned())?,
+                        count: *count,
+                        range: *range,
+                    })
+                }
+                DummyContextCondition::All(conditions) => {
+                    Ok(ContextCondition::All(convert_all(conditions)?))
+                }
+                DummyContextCondition::Any(conditions) => {
+                    Ok(ContextCondition::Any(convert_all(conditions)?))
+                }
+                DummyContextCondition::Not(condition) => Ok(ContextCondition::Not(Box::new(
+                    convert_condition(specs, arts, condition)?,
+                ))),
+            }
+        }
+
         fn convert_replacement(
             specs: &M,
             arts: &M,
@@ -337,6 +502,20 @@ impl TryFrom<DummySwarmGenome> for SwarmGenome {
                         }
                     }
                 }
+                DummyReplacement::Pattern(ident, count, pattern) => {
+                    match convert_identifier(specs, arts, ident.0.to_owned())? {
+                        SurroundingIndex::Artifact(_) => {
+                            Err(format!("Artifact {} is not supported in Pattern!", ident.0))
+                        }
+                        SurroundingIndex::Agent(index) => {
+                            Ok(Replacement::Pattern(index, *count, *pattern))
+                        }
+                    }
+                }
+                DummyReplacement::Transformed(rep, transform) => Ok(Replacement::Transformed(
+                    Box::new(convert_replacement(specs, arts, rep)?),
+                    *transform,
+                )),
             }
         }
 
@@ -386,6 +565,13 @@ impl TryFrom<DummySwarmGenome> for SwarmGenome {
             .map(|(a, b)| (b.to_owned(), a))
             .collect();
 
+        let field_names: M = dummy
+            .fields
+            .keys()
+            .enumerate()
+            .map(|(a, b)| (b.to_owned(), a))
+            .collect();
+
         //dbg!(&species_names);
         //dbg!(&artifact_names);
 
@@ -403,6 +589,21 @@ impl TryFrom<DummySwarmGenome> for SwarmGenome {
                 })
                 .collect::<Result<HashMap<SurroundingIndex, InfluenceFactor>, Self::Error>>()?;
 
+            let fields = dummy_spec
+                .fields
+                .iter()
+                .map(|(name, interaction)| {
+                    field_names
+                        .get(name)
+                        .map(|field| FieldInteraction {
+                            field: *field,
+                            deposit: interaction.deposit,
+                            follow: interaction.follow,
+                        })
+                        .ok_or_else(|| format!("Field {} is not defined", name))
+                })
+                .collect::<Result<Vec<_>, Self::Error>>()?;
+
             let rules = dummy_spec
                 .rules
                 .iter()
@@ -427,12 +628,22 @@ impl TryFrom<DummySwarmGenome> for SwarmGenome {
                     );
                     let replacement = replacement_result?;
 
+                    let conditions = dummy_rule
+                        .conditions
+                        .iter()
+                        .map(|condition| {
+                            convert_condition(&species_names, &artifact_names, condition)
+                        })
+                        .collect::<Result<Vec<_>, Self::Error>>()?;
+
                     let rule = ContextRule {
                         persist: dummy_rule.persist,
                         range: dummy_rule.range,
                         weight: dummy_rule.weight,
                         context,
                         replacement,
+                        conditions,
+                        guards: dummy_rule.guards.clone(),
                     };
                     Ok(rule)
                 })
@@ -457,31 +668,27 @@ impl TryFrom<DummySwarmGenome> for SwarmGenome {
             };
 
             let species = Species {
-                alignment: dummy_spec.urges.alignment,
+                urges: dummy_spec.urges.weights.clone(),
                 axis_constraint: Vector3::from(dummy_spec.axis_constraint),
-                center: dummy_spec.urges.center,
-                cohesion: dummy_spec.urges.cohesion,
                 bias: Vector3::from(dummy_spec.urges.bias),
-                gradient: dummy_spec.urges.gradient,
-                slope: dummy_spec.urges.slope,
-                normal: dummy_spec.urges.normal,
                 pacekeeping: dummy_spec.urges.pacekeeping,
                 normal_speed: dummy_spec.normal_speed,
                 hand_down_seed: dummy_spec.hand_down_seed,
+                fields,
                 index: SpeciesIndex(*id),
                 influenced_by: influences,
-                floor: dummy_spec.urges.floor,
                 max_speed: dummy_spec.max_speed,
                 max_acceleration: dummy_spec.max_acceleration,
                 noclip: dummy_spec.noclip,
-                randomness: dummy_spec.urges.randomness,
                 rules,
                 energy,
                 sep_distance: dummy_spec.sep_distance,
-                separation: dummy_spec.urges.separation,
                 view_distance: dummy_spec.view_distance,
                 view_angle: dummy_spec.view_angle,
                 color_index: dummy_spec.color_index,
+                boundary: dummy_spec.boundary,
+                susceptibility: dummy_spec.susceptibility,
+                strategy: dummy_spec.strategy.map(ApplicationStrategy::from),
             };
 
             species_results[*id] = Ok(species);
@@ -495,28 +702,77 @@ impl TryFrom<DummySwarmGenome> for SwarmGenome {
             artifact_results[*id] = Ok(dummy_art.clone())
         }
 
-        let mut terrain_art_results: Vec<Result<f32, Self::Error>> =
+        let mut terrain_art_results: Vec<Result<(f32, InfluenceBehavior), Self::Error>> =
             vec![Err("No terrain influence initialized".to_string()); artifact_names.len()];
-        let mut terrain_spec_results: Vec<Result<f32, Self::Error>> =
+        let mut terrain_spec_results: Vec<Result<(f32, InfluenceBehavior), Self::Error>> =
             vec![Err("No terrain influence initialized".to_string()); species_names.len()];
 
+        let terrain_influence = |name: &String| {
+            dummy
+                .terrain
+                .influenced_by
+                .get(name)
+                .map_or((0.0, InfluenceBehavior::default()), |inf| inf.split())
+        };
+
         for (name, id) in &species_names {
-            let dummy_inf = dummy.terrain.influenced_by.get(name).unwrap_or(&0.0);
-            terrain_spec_results[*id] = Ok(dummy_inf.clone())
+            terrain_spec_results[*id] = Ok(terrain_influence(name))
         }
 
         for (name, id) in &artifact_names {
-            let dummy_inf = dummy.terrain.influenced_by.get(name).unwrap_or(&0.0);
-            terrain_art_results[*id] = Ok(dummy_inf.clone())
+            terrain_art_results[*id] = Ok(terrain_influence(name))
         }
 
-        let terrain_spec = terrain_spec_results
+        let (terrain_spec, terrain_spec_behaviors): (Vec<_>, Vec<_>) = terrain_spec_results
             .into_iter()
-            .collect::<Result<Vec<_>, _>>()?;
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .unzip();
 
-        let terrain_art = terrain_art_results
+        let (terrain_art, terrain_art_behaviors): (Vec<_>, Vec<_>) = terrain_art_results
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?
             .into_iter()
-            .collect::<Result<Vec<_>, _>>()?;
+            .unzip();
+
+        let terrain_x_size = dummy.terrain.x_size.unwrap_or(dummy.terrain.size);
+        let terrain_z_size = dummy.terrain.z_size.unwrap_or(dummy.terrain.size);
+        if terrain_x_size == 0 || terrain_z_size == 0 {
+            return Err(format!(
+                "Terrain needs buoys along both axes, but has {}x{}",
+                terrain_x_size, terrain_z_size
+            ));
+        }
+
+        if let Some(bounds) = &dummy.bounds {
+            bounds.validate()?;
+        }
+
+        for (name, field) in &dummy.fields {
+            let spacing = field.spacing.unwrap_or(dummy.terrain.spacing);
+            if spacing <= 0.0 || spacing.is_nan() {
+                return Err(format!(
+                    "Field {} needs a positive spacing, but has {}",
+                    name, spacing
+                ));
+            }
+        }
+
+        for force in &dummy.forces {
+            force.validate()?;
+        }
+
+        if dummy.integration.substeps == 0 {
+            return Err("Integration needs at least one substep".to_string());
+        }
+
+        let cell_size = dummy.grid.cell_size.unwrap_or(dummy.terrain.spacing);
+        if cell_size <= 0.0 || cell_size.is_nan() {
+            return Err(format!(
+                "Grid cells need a positive size, but have {}",
+                cell_size
+            ));
+        }
 
         Ok(SwarmGenome {
             species_map: species_results.into_iter().collect::<Result<Vec<_>, _>>()?,
@@ -525,9 +781,168 @@ impl TryFrom<DummySwarmGenome> for SwarmGenome {
                 .collect::<Result<Vec<_>, _>>()?,
             strategy: ApplicationStrategy::from(dummy.strategy),
             start_dist: convert_distribution(&species_names, &artifact_names, &dummy.start_dist)?,
-            terrain_size: dummy.terrain.size,
+            terrain_size: terrain_x_size,
+            terrain_z_size: Some(terrain_z_size),
             terrain_influences: (terrain_spec, terrain_art),
+            terrain_behaviors: (terrain_spec_behaviors, terrain_art_behaviors),
             terrain_spacing: dummy.terrain.spacing,
+            terrain_origin: (dummy.terrain.origin[0], dummy.terrain.origin[1]),
+            terrain_update: dummy.terrain.update,
+            terrain_heightmap: dummy
+                .terrain
+                .heightmap
+                .map(|config| match files.heightmap.take() {
+                    Some(heightmap) => Ok(TerrainHeightmap { config, heightmap }),
+                    None => Err(format!(
+                        "Heightmap {} has not been loaded",
+                        config.path.display()
+                    )),
+                })
+                .transpose()?,
+            terrain_erosion: dummy.terrain.erosion,
+            grid: dummy.grid,
+            bounds: dummy.bounds,
+            obstacles: dummy
+                .obstacles
+                .iter()
+                .map(|config| match config {
+                    ObstacleConfig::Mesh {
+                        path,
+                        offset,
+                        scale,
+                    } => match files.meshes.get(path) {
+                        Some(text) => Obstacle::from_obj(text, Vector3::from(*offset), *scale),
+                        None => Err(format!(
+                            "Obstacle mesh {} has not been loaded",
+                            path.display()
+                        )),
+                    },
+                    primitive => Obstacle::from_primitive(primitive),
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            fields: {
+                let mut fields = vec![FieldConfig::default(); field_names.len()];
+                for (name, id) in &field_names {
+                    fields[*id] = dummy.fields[name];
+                }
+                fields
+            },
+            forces: dummy.forces,
+            integration: dummy.integration,
         })
     }
 }
+
+/// Converts the context sample after applying `change` to it.
+#[cfg(test)]
+fn convert_changed(change: impl FnOnce(&mut DummySwarmGenome)) -> Result<SwarmGenome, String> {
+    convert_changed_with(GenomeFiles::default(), change)
+}
+
+#[cfg(test)]
+fn convert_changed_with(
+    files: GenomeFiles,
+    change: impl FnOnce(&mut DummySwarmGenome),
+) -> Result<SwarmGenome, String> {
+    let json = std::fs::read_to_string(crate::utils::sample_path("context_config.json")).unwrap();
+    let mut dummy: DummySwarmGenome = serde_json::from_str(&json).unwrap();
+    change(&mut dummy);
+    SwarmGenome::try_from((dummy, files))
+}
+
+#[test]
+fn test_grid_cells_need_a_positive_size() {
+    assert!(convert_changed(|_| ()).is_ok());
+    assert!(convert_changed(|dummy| dummy.grid.cell_size = Some(0.0)).is_err());
+    assert!(convert_changed(|dummy| dummy.grid.cell_size = Some(-2.0)).is_err());
+}
+
+#[test]
+fn test_bounds_need_a_positive_extent() {
+    let bounds = |min, max| {
+        Some(bounds::WorldBounds {
+            min: [-10.0, min, -10.0],
+            max: [10.0, max, 10.0],
+        })
+    };
+    assert!(convert_changed(|dummy| dummy.bounds = bounds(0.0, 20.0)).is_ok());
+    assert!(convert_changed(|dummy| dummy.bounds = bounds(20.0, 0.0)).is_err());
+    assert!(convert_changed(|dummy| dummy.bounds = bounds(5.0, 5.0)).is_err());
+    assert!(convert_changed(|dummy| dummy.bounds = bounds(f32::NAN, 5.0)).is_err());
+}
+
+#[test]
+fn test_obstacles_need_to_be_valid_and_loaded() {
+    let inverted = obstacle::ObstacleConfig::Box {
+        min: [1.0, 0.0, 0.0],
+        max: [0.0, 1.0, 1.0],
+    };
+    assert!(convert_changed(|dummy| dummy.obstacles = vec![inverted]).is_err());
+
+    let mesh = obstacle::ObstacleConfig::Mesh {
+        path: "tetrahedron.obj".into(),
+        offset: [0.0, 0.0, 0.0],
+        scale: 1.0,
+    };
+    assert!(convert_changed(|dummy| dummy.obstacles = vec![mesh.clone()]).is_err());
+    let mut files = GenomeFiles::default();
+    files.meshes.insert(
+        "tetrahedron.obj".into(),
+        "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\nf 1 3 2\nf 1 2 4\nf 1 4 3\nf 2 3 4\n".to_string(),
+    );
+    assert!(convert_changed_with(files, |dummy| dummy.obstacles = vec![mesh]).is_ok());
+}
+
+#[test]
+fn test_fields_need_a_positive_spacing() {
+    let field = |spacing| {
+        move |dummy: &mut DummySwarmGenome| {
+            let config = field::FieldConfig {
+                spacing: Some(spacing),
+                ..Default::default()
+            };
+            dummy.fields.insert("scent".to_string(), config);
+        }
+    };
+    assert!(convert_changed(field(2.0)).is_ok());
+    assert!(convert_changed(field(0.0)).is_err());
+}
+
+#[test]
+fn test_turbulence_needs_a_positive_scale() {
+    let turbulence = |scale| {
+        move |dummy: &mut DummySwarmGenome| {
+            let kind = force::ForceKind::Turbulence {
+                strength: 1.0,
+                scale,
+                speed: 0.0,
+                seed: 0,
+            };
+            dummy.forces = vec![force::ForceField { kind, pulse: None }];
+        }
+    };
+    assert!(convert_changed(turbulence(5.0)).is_ok());
+    assert!(convert_changed(turbulence(0.0)).is_err());
+}
+
+#[test]
+fn test_heightmaps_need_to_be_loaded() {
+    let config = terrain::HeightmapConfig {
+        path: "heights.pgm".into(),
+        scale: 1.0,
+        offset: 0.0,
+        anchor: 0.0,
+    };
+    assert!(convert_changed(|dummy| dummy.terrain.heightmap = Some(config.clone())).is_err());
+    let files = GenomeFiles {
+        heightmap: Some(terrain::Heightmap::new(2, 2, vec![0.0; 4]).unwrap()),
+        ..Default::default()
+    };
+    assert!(convert_changed_with(files, |dummy| dummy.terrain.heightmap = Some(config)).is_ok());
+}
+
+#[test]
+fn test_integration_needs_a_substep() {
+    assert!(convert_changed(|dummy| dummy.integration.substeps = 1).is_ok());
+    assert!(convert_changed(|dummy| dummy.integration.substeps = 0).is_err());
+}
@@ -0,0 +1,121 @@
+use cgmath::Vector3;
+use serde::{Deserialize, Serialize};
+
+/// Axis aligned box which agents are kept in. Matching its x and z extent to the terrain keeps
+/// agents off the plateaus beyond the outermost buoys.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct WorldBounds {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+/// What happens to an agent of a species which leaves the world bounds.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum BoundaryPolicy {
+    /// The agent enters the bounds again on the opposite side.
+    Wrap,
+    /// The agent is mirrored back into the bounds and its velocity reversed along that axis.
+    Reflect,
+    /// The agent is held at the boundary and loses its velocity along that axis.
+    #[default]
+    Clamp,
+    /// The agent is removed from the world.
+    Kill,
+}
+
+impl WorldBounds {
+    /// Err if the box is empty or flat along an axis.
+    pub fn validate(&self) -> Result<(), String> {
+        if (0..3).all(|axis| self.min[axis] < self.max[axis]) {
+            Ok(())
+        } else {
+            Err(format!(
+                "World bounds need a min below the max on every axis, but span {:?} to {:?}",
+                self.min, self.max
+            ))
+        }
+    }
+
+    pub fn contains(&self, position: Vector3<f32>) -> bool {
+        (0..3).all(|axis| self.min[axis] <= position[axis] && position[axis] <= self.max[axis])
+    }
+
+    /// Position and velocity of an agent after applying `policy`, `None` if it is killed.
+    pub fn confine(
+        &self,
+        policy: BoundaryPolicy,
+        mut position: Vector3<f32>,
+        mut velocity: Vector3<f32>,
+    ) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        if self.contains(position) {
+            return Some((position, velocity));
+        }
+
+        for axis in 0..3 {
+            let (min, max) = (self.min[axis], self.max[axis]);
+            let pos = position[axis];
+            if min <= pos && pos <= max {
+                continue;
+            }
+
+            match policy {
+                BoundaryPolicy::Kill => return None,
+                BoundaryPolicy::Wrap if max > min => {
+                    position[axis] = min + (pos - min).rem_euclid(max - min);
+                }
+                BoundaryPolicy::Reflect => {
+                    let mirrored = if pos < min {
+                        2.0 * min - pos
+                    } else {
+                        2.0 * max - pos
+                    };
+                    position[axis] = mirrored.clamp(min, max);
+                    velocity[axis] = -velocity[axis];
+                }
+                BoundaryPolicy::Wrap | BoundaryPolicy::Clamp => {
+                    position[axis] = pos.clamp(min, max);
+                    velocity[axis] = 0.0;
+                }
+            }
+        }
+        Some((position, velocity))
+    }
+}
+
+#[test]
+fn test_boundary_policies() {
+    let bounds = WorldBounds {
+        min: [-10.0, 0.0, -10.0],
+        max: [10.0, 20.0, 10.0],
+    };
+    let inside = Vector3::new(1.0, 2.0, 3.0);
+    let outside = Vector3::new(12.0, 5.0, -11.0);
+    let velocity = Vector3::new(3.0, 1.0, -2.0);
+
+    for policy in &[
+        BoundaryPolicy::Wrap,
+        BoundaryPolicy::Reflect,
+        BoundaryPolicy::Clamp,
+        BoundaryPolicy::Kill,
+    ] {
+        assert_eq!(
+            bounds.confine(*policy, inside, velocity),
+            Some((inside, velocity))
+        );
+    }
+
+    let confine = |policy| bounds.confine(policy, outside, velocity);
+    assert_eq!(
+        confine(BoundaryPolicy::Wrap),
+        Some((Vector3::new(-8.0, 5.0, 9.0), velocity))
+    );
+    assert_eq!(
+        confine(BoundaryPolicy::Reflect),
+        Some((Vector3::new(8.0, 5.0, -9.0), Vector3::new(-3.0, 1.0, 2.0)))
+    );
+    assert_eq!(
+        confine(BoundaryPolicy::Clamp),
+        Some((Vector3::new(10.0, 5.0, -10.0), Vector3::new(0.0, 1.0, 0.0)))
+    );
+    assert_eq!(confine(BoundaryPolicy::Kill), None);
+}
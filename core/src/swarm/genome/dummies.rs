@@ -15,29 +15,77 @@ pub struct DummySwarmGenome {
     pub start_dist: DummyDistribution,
     pub strategy: DummyApplicationStrategy,
     pub terrain: TerrainConfig,
+    #[serde(default)]
+    pub grid: super::GridConfig,
+    #[serde(default)]
+    pub bounds: Option<super::bounds::WorldBounds>,
+    #[serde(default)]
+    pub obstacles: Vec<super::obstacle::ObstacleConfig>,
+    #[serde(default)]
+    pub fields: HashMap<String, super::field::FieldConfig>,
+    #[serde(default)]
+    pub forces: Vec<super::force::ForceField>,
+    #[serde(default)]
+    pub integration: super::integration::Integration,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct TerrainConfig {
+    /// Buoys along both axes, unless overridden by `x_size` or `z_size`.
+    #[serde(default)]
     pub size: usize,
+    #[serde(default)]
+    pub x_size: Option<usize>,
+    #[serde(default)]
+    pub z_size: Option<usize>,
     pub spacing: f32,
-    pub influenced_by: HashMap<String, InfluenceFactor>,
+    /// Position of the terrain center on the x and z axis.
+    #[serde(default)]
+    pub origin: [f32; 2],
+    pub influenced_by: HashMap<String, DummyTerrainInfluence>,
+    #[serde(default)]
+    pub update: super::terrain::TerrainUpdate,
+    #[serde(default)]
+    pub heightmap: Option<super::terrain::HeightmapConfig>,
+    #[serde(default)]
+    pub erosion: super::terrain::TerrainErosion,
+}
+
+/// Either a plain factor, which pulls with the default falloff, or a factor with its behavior.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(untagged)]
+pub enum DummyTerrainInfluence {
+    Factor(InfluenceFactor),
+    Detailed {
+        factor: InfluenceFactor,
+        #[serde(default)]
+        mode: super::terrain::InfluenceMode,
+        #[serde(default)]
+        falloff: super::terrain::Falloff,
+    },
+}
+
+impl DummyTerrainInfluence {
+    pub fn split(&self) -> (InfluenceFactor, super::terrain::InfluenceBehavior) {
+        match *self {
+            Self::Factor(factor) => (factor, Default::default()),
+            Self::Detailed {
+                factor,
+                mode,
+                falloff,
+            } => (factor, super::terrain::InfluenceBehavior { mode, falloff }),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct Urges {
     #[serde(default)]
     pub bias: [Factor; 3],
-    pub separation: Factor,
-    pub alignment: Factor,
-    pub cohesion: Factor,
-    pub randomness: Factor,
-    pub center: Factor,
     pub pacekeeping: Factor,
-    pub floor: Factor,
-    pub gradient: Factor,
-    pub normal: Factor,
-    pub slope: Factor,
+    /// Weights of the urges in `urge::URGES` by name, missing urges have their default weight.
+    #[serde(flatten)]
+    pub weights: crate::swarm::urge::UrgeWeights,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
@@ -57,8 +105,26 @@ pub struct DummySpecies {
     pub energy: DummyEnergy,
     #[serde(default = "crate::utils::no")]
     pub hand_down_seed: bool,
+    #[serde(default)]
+    pub fields: HashMap<String, DummyFieldInteraction>,
     pub rules: Vec<DummyContextRule>,
     pub color_index: usize,
+    #[serde(default)]
+    pub boundary: super::bounds::BoundaryPolicy,
+    #[serde(default = "crate::utils::one")]
+    pub susceptibility: Factor,
+    /// Overrides the strategy of the genome for this species.
+    #[serde(default)]
+    pub strategy: Option<DummyApplicationStrategy>,
+}
+
+/// How the agents of a species interact with a named field.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub struct DummyFieldInteraction {
+    #[serde(default)]
+    pub deposit: Factor,
+    #[serde(default)]
+    pub follow: Factor,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -70,6 +136,8 @@ pub struct DummyContextRule {
     #[serde(default = "crate::utils::no")]
     pub persist: bool,
     pub replacement: DummyReplacement,
+    pub conditions: Vec<DummyContextCondition>,
+    pub guards: Vec<super::replacement::StateGuard>,
 }
 
 impl Default for DummyContextRule {
@@ -80,16 +148,48 @@ impl Default for DummyContextRule {
             persist: false,
             replacement: DummyReplacement::None,
             range: 5.0,
+            conditions: Vec::new(),
+            guards: Vec::new(),
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+/// Condition on the actors around an agent. Ranges default to the range of the rule.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum DummyContextCondition {
+    Absent {
+        of: Identifier,
+        #[serde(default)]
+        range: Option<f32>,
+    },
+    AtLeast {
+        of: Identifier,
+        count: usize,
+        #[serde(default)]
+        range: Option<f32>,
+    },
+    AtMost {
+        of: Identifier,
+        count: usize,
+        #[serde(default)]
+        range: Option<f32>,
+    },
+    All(Vec<DummyContextCondition>),
+    Any(Vec<DummyContextCondition>),
+    Not(Box<DummyContextCondition>),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum DummyReplacement {
     None,
     Simple(Vec<Identifier>),
     Multi(Vec<DummyReplacement>),
     Spread(Identifier, usize, usize),
+    Pattern(Identifier, usize, super::replacement::SpreadPattern),
+    Transformed(
+        Box<DummyReplacement>,
+        super::replacement::OffspringTransform,
+    ),
 }
 
 impl Default for DummyReplacement {
@@ -103,7 +203,9 @@ impl DummyReplacement {
             Self::None => false,
             Self::Simple(ids) => ids.iter().any(|id| id.0.eq(other)),
             Self::Spread(id, _, _) => id.0.eq(other),
+            Self::Pattern(id, _, _) => id.0.eq(other),
             Self::Multi(reps) => reps.iter().any(|rep| rep.contains(other)),
+            Self::Transformed(rep, _) => rep.contains(other),
         }
     }
 }
@@ -132,6 +234,8 @@ pub struct DummyApplicationStrategy {
     pub every: usize,
     #[serde(default)]
     pub offset: Option<usize>,
+    #[serde(default = "crate::utils::one")]
+    pub probability: f32,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
@@ -142,7 +246,7 @@ pub struct DummyEnergy {
     pub for_offspring: super::energy::OffspringEnergy,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum DummyZeroEnergy {
     Die,
     Replace(u16, DummyReplacement),
@@ -166,7 +270,10 @@ pub fn example_dummy_genome() -> DummySwarmGenome {
     let mut artifact_map = HashMap::new();
     let mut terrain_map = HashMap::new();
 
-    let mut species = DummySpecies::default();
+    let mut species = DummySpecies {
+        susceptibility: 1.0,
+        ..Default::default()
+    };
 
     species.rules.push(DummyContextRule {
         context: vec![],
@@ -174,6 +281,8 @@ pub fn example_dummy_genome() -> DummySwarmGenome {
         weight: 2.0,
         persist: true,
         replacement: DummyReplacement::Simple(vec![Identifier::new("a0")]),
+        conditions: vec![],
+        guards: vec![],
     });
     species.influenced_by.insert(Identifier::new("seed"), 2.0);
 
@@ -181,20 +290,72 @@ pub fn example_dummy_genome() -> DummySwarmGenome {
 
     artifact_map.insert("a0".to_string(), super::ArtifactType::default());
 
-    terrain_map.insert("a0".to_string(), 0.0);
+    terrain_map.insert("a0".to_string(), DummyTerrainInfluence::Factor(0.0));
 
     DummySwarmGenome {
         strategy: DummyApplicationStrategy {
             every: 10,
             offset: None,
+            probability: 1.0,
         },
         start_dist: DummyDistribution::Single([0.0, 0.0, 0.0], Identifier::new("seed")),
         species_map,
         artifact_map,
         terrain: TerrainConfig {
             size: 31,
+            x_size: None,
+            z_size: None,
             spacing: 10.0,
+            origin: [0.0, 0.0],
             influenced_by: terrain_map,
+            update: super::terrain::TerrainUpdate::default(),
+            heightmap: None,
+            erosion: super::terrain::TerrainErosion::default(),
         },
+        grid: super::GridConfig::default(),
+        bounds: None,
+        obstacles: vec![],
+        fields: HashMap::new(),
+        forces: vec![],
+        integration: super::integration::Integration::default(),
     }
 }
+
+#[test]
+fn test_terrain_influence_formats() {
+    use super::terrain::{Falloff, InfluenceBehavior, InfluenceMode};
+
+    let influences: HashMap<String, DummyTerrainInfluence> = serde_json::from_str(
+        r#"{
+            "plain": 2.0,
+            "water": { "factor": 0.5, "mode": "Carve", "falloff": { "Gaussian": 10.0 } },
+            "farm": { "factor": 1.0, "mode": { "Level": 3.0 } }
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        influences["plain"].split(),
+        (2.0, InfluenceBehavior::default())
+    );
+    assert_eq!(
+        influences["water"].split(),
+        (
+            0.5,
+            InfluenceBehavior {
+                mode: InfluenceMode::Carve,
+                falloff: Falloff::Gaussian(10.0)
+            }
+        )
+    );
+    assert_eq!(
+        influences["farm"].split(),
+        (
+            1.0,
+            InfluenceBehavior {
+                mode: InfluenceMode::Level(3.0),
+                falloff: Falloff::InversePower
+            }
+        )
+    );
+}
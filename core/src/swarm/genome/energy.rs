@@ -28,7 +28,7 @@ pub enum MovementEnergy {
     None,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum ZeroEnergy {
     Die,
     Replace(u16, super::replacement::Replacement),
@@ -101,6 +101,7 @@ impl ZeroEnergy {
         parent: &Agent,
         parent_species: &Species,
         uid_gen: &mut crate::utils::UidGen,
+        rnd: &mut impl rand::Rng,
     ) -> (Vec<Agent>, Vec<Artifact>) {
         match self {
             Self::Replace(energy, replacement) => {
@@ -109,6 +110,7 @@ impl ZeroEnergy {
                     parent_species,
                     *energy as f32,
                     uid_gen,
+                    rnd,
                 );
                 for ag in &mut reps.0 {
                     ag.last = None;
@@ -118,6 +120,12 @@ impl ZeroEnergy {
             _ => (vec![], vec![]),
         }
     }
+    pub fn required_uids(&self) -> usize {
+        match self {
+            Self::Replace(_, replacement) => replacement.count_replacements(),
+            _ => 0,
+        }
+    }
     pub fn get_param(&self) -> u16 {
         match self {
             ZeroEnergy::Die => 0,
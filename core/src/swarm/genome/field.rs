@@ -0,0 +1,169 @@
+use cgmath::Vector3;
+use serde::{Deserialize, Serialize};
+
+/// A scalar field over the terrain, like a pheromone, which agents deposit into and follow.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub struct FieldConfig {
+    /// Distance between the grid points. Falls back to the terrain spacing if unset.
+    #[serde(default)]
+    pub spacing: Option<f32>,
+    /// Fraction of the way every grid point moves toward the average of its neighbours per
+    /// step, within `0..=1`.
+    #[serde(default)]
+    pub diffusion: f32,
+    /// Fraction of the field lost per step, within `0..=1`.
+    #[serde(default)]
+    pub evaporation: f32,
+}
+
+/// How the agents of a species interact with a field.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct FieldInteraction {
+    /// Index of the field in `SwarmGenome::fields`.
+    pub field: usize,
+    /// Amount added to the field at the agent position per step.
+    pub deposit: f32,
+    /// Weight of the pull along the gradient of the field, which is summed up over all
+    /// fields into the `trail` urge.
+    pub follow: f32,
+}
+
+/// Values of a field on a grid spanning the terrain.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ScalarField {
+    values: Vec<f32>,
+    x_size: usize,
+    z_size: usize,
+    spacing: f32,
+    /// Position of the first grid point on the x and z axis.
+    min: (f32, f32),
+    diffusion: f32,
+    evaporation: f32,
+}
+
+impl ScalarField {
+    /// Zero field covering `extent` along x and z from `min`.
+    pub fn new(config: &FieldConfig, min: (f32, f32), extent: (f32, f32), spacing: f32) -> Self {
+        let spacing = config.spacing.unwrap_or(spacing);
+        let points = |extent: f32| (extent / spacing).ceil().max(0.0) as usize + 1;
+        let (x_size, z_size) = (points(extent.0), points(extent.1));
+        ScalarField {
+            values: vec![0.0; x_size * z_size],
+            x_size,
+            z_size,
+            spacing,
+            min,
+            diffusion: config.diffusion,
+            evaporation: config.evaporation,
+        }
+    }
+
+    /// Surrounding grid points of `position` with their bilinear weights.
+    fn corners(&self, position: Vector3<f32>) -> [(usize, f32); 4] {
+        let grid = |pos: f32, min: f32, size: usize| {
+            let g = ((pos - min) / self.spacing).clamp(0.0, (size - 1) as f32);
+            let low = (g.floor() as usize).min(size.saturating_sub(2));
+            (low, (low + 1).min(size - 1), g - low as f32)
+        };
+        let (x0, x1, fx) = grid(position.x, self.min.0, self.x_size);
+        let (z0, z1, fz) = grid(position.z, self.min.1, self.z_size);
+        let at = |x: usize, z: usize| x * self.z_size + z;
+        [
+            (at(x0, z0), (1.0 - fx) * (1.0 - fz)),
+            (at(x1, z0), fx * (1.0 - fz)),
+            (at(x0, z1), (1.0 - fx) * fz),
+            (at(x1, z1), fx * fz),
+        ]
+    }
+
+    /// Bilinearly interpolated value at the horizontal position of `position`.
+    pub fn sample(&self, position: Vector3<f32>) -> f32 {
+        self.corners(position)
+            .iter()
+            .map(|(index, weight)| self.values[*index] * weight)
+            .sum()
+    }
+
+    /// Horizontal gradient at `position`, pointing toward higher values.
+    pub fn gradient(&self, position: Vector3<f32>) -> Vector3<f32> {
+        let offset = self.spacing * 0.5;
+        let dx = Vector3::new(offset, 0.0, 0.0);
+        let dz = Vector3::new(0.0, 0.0, offset);
+        Vector3::new(
+            self.sample(position + dx) - self.sample(position - dx),
+            0.0,
+            self.sample(position + dz) - self.sample(position - dz),
+        ) / self.spacing
+    }
+
+    /// Spreads `amount` over the grid points around `position`.
+    pub fn deposit(&mut self, position: Vector3<f32>, amount: f32) {
+        if !(position.x.is_finite() && position.z.is_finite()) {
+            return;
+        }
+        for (index, weight) in self.corners(position).iter() {
+            self.values[*index] += amount * weight;
+        }
+    }
+
+    /// Diffuses and then evaporates the field once.
+    pub fn decay(&mut self) {
+        let (x_size, z_size) = (self.x_size, self.z_size);
+        if self.diffusion != 0.0 {
+            let values = &self.values;
+            let diffused = (0..values.len())
+                .map(|i| {
+                    let (x, z) = (i / z_size, i % z_size);
+                    let neighbours = [
+                        (x > 0).then(|| i - z_size),
+                        (x + 1 < x_size).then(|| i + z_size),
+                        (z > 0).then(|| i - 1),
+                        (z + 1 < z_size).then(|| i + 1),
+                    ];
+                    let (sum, count) = neighbours
+                        .iter()
+                        .flatten()
+                        .fold((0.0, 0), |(sum, count), n| (sum + values[*n], count + 1));
+                    if count == 0 {
+                        values[i]
+                    } else {
+                        values[i] + self.diffusion * (sum / count as f32 - values[i])
+                    }
+                })
+                .collect();
+            self.values = diffused;
+        }
+        if self.evaporation != 0.0 {
+            let kept = 1.0 - self.evaporation;
+            self.values.iter_mut().for_each(|value| *value *= kept);
+        }
+    }
+}
+
+#[test]
+fn test_scalar_field() {
+    let config = FieldConfig {
+        spacing: Some(1.0),
+        diffusion: 0.5,
+        evaporation: 0.0,
+    };
+    let mut field = ScalarField::new(&config, (-5.0, -5.0), (10.0, 10.0), 3.0);
+    assert_eq!((field.x_size, field.z_size), (11, 11));
+
+    let center = Vector3::new(0.5, 7.0, 0.0);
+    field.deposit(center, 4.0);
+    assert_eq!(field.sample(center), 2.0);
+    assert_eq!(field.values.iter().sum::<f32>(), 4.0);
+
+    for _ in 0..3 {
+        field.decay();
+    }
+    assert!((field.values.iter().sum::<f32>() - 4.0).abs() < 1e-5);
+    let gradient = field.gradient(Vector3::new(2.0, 0.0, 1.0));
+    assert!(gradient.x < 0.0 && gradient.z < 0.0);
+
+    field.diffusion = 0.0;
+    field.evaporation = 0.25;
+    field.decay();
+    assert!((field.values.iter().sum::<f32>() - 3.0).abs() < 1e-5);
+}
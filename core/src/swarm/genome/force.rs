@@ -0,0 +1,207 @@
+use cgmath::{InnerSpace, Vector3};
+use serde::{Deserialize, Serialize};
+
+/// A force acting on every agent, scaled by the susceptibility of its species.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct ForceField {
+    pub kind: ForceKind,
+    /// Lets the strength of the force oscillate over the iterations.
+    #[serde(default)]
+    pub pulse: Option<Pulse>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum ForceKind {
+    /// The same force everywhere, like wind or gravity.
+    Uniform([f32; 3]),
+    /// Pulls toward `center`, or pushes away for a negative strength.
+    Radial {
+        center: [f32; 3],
+        strength: f32,
+        /// The force is divided by `(1 + distance)^falloff`.
+        #[serde(default)]
+        falloff: f32,
+    },
+    /// Swirls counter-clockwise around the line through `center` along `axis`.
+    Vortex {
+        center: [f32; 3],
+        axis: [f32; 3],
+        strength: f32,
+        /// The force is divided by `(1 + distance to the axis)^falloff`.
+        #[serde(default)]
+        falloff: f32,
+    },
+    /// Smooth pseudo random force, changing over distances of about `scale`.
+    Turbulence {
+        strength: f32,
+        scale: f32,
+        /// Distance in noise space the turbulence drifts per iteration.
+        #[serde(default)]
+        speed: f32,
+        #[serde(default)]
+        seed: u32,
+    },
+}
+
+/// Scales a force by `1 + amplitude * sin(2 pi (iteration / period + phase))`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Pulse {
+    /// Iterations per oscillation.
+    pub period: f32,
+    #[serde(default = "crate::utils::one")]
+    pub amplitude: f32,
+    /// Offset of the oscillation as a fraction of the period.
+    #[serde(default)]
+    pub phase: f32,
+}
+
+impl ForceField {
+    /// Err if the force would not be finite everywhere.
+    pub fn validate(&self) -> Result<(), String> {
+        match self.kind {
+            ForceKind::Turbulence { scale, .. } if scale <= 0.0 || scale.is_nan() => Err(format!(
+                "Turbulence needs a positive scale, but has {}",
+                scale
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Force at `position` after a time of `iteration` steps.
+    pub fn force_at(&self, position: Vector3<f32>, iteration: f32) -> Vector3<f32> {
+        let force = match self.kind {
+            ForceKind::Uniform(force) => Vector3::from(force),
+            ForceKind::Radial {
+                center,
+                strength,
+                falloff,
+            } => {
+                let diff = Vector3::from(center) - position;
+                let distance = diff.magnitude();
+                if distance == 0.0 {
+                    return Vector3::new(0.0, 0.0, 0.0);
+                }
+                diff / distance * strength / (1.0 + distance).powf(falloff)
+            }
+            ForceKind::Vortex {
+                center,
+                axis,
+                strength,
+                falloff,
+            } => {
+                let axis = crate::utils::safe_normalize(Vector3::from(axis));
+                let offset = position - Vector3::from(center);
+                let radial = offset - axis * offset.dot(axis);
+                let distance = radial.magnitude();
+                if distance == 0.0 {
+                    return Vector3::new(0.0, 0.0, 0.0);
+                }
+                axis.cross(radial / distance) * strength / (1.0 + distance).powf(falloff)
+            }
+            ForceKind::Turbulence {
+                strength,
+                scale,
+                speed,
+                seed,
+            } => {
+                let drift = iteration * speed;
+                let p = position / scale + Vector3::new(drift, drift, drift);
+                Vector3::new(
+                    value_noise(seed, p),
+                    value_noise(seed.wrapping_add(1), p),
+                    value_noise(seed.wrapping_add(2), p),
+                ) * strength
+            }
+        };
+
+        match self.pulse {
+            Some(pulse) if pulse.period != 0.0 => {
+                let angle = 2.0 * std::f32::consts::PI * (iteration / pulse.period + pulse.phase);
+                force * (1.0 + pulse.amplitude * angle.sin())
+            }
+            _ => force,
+        }
+    }
+}
+
+/// Pseudo random value within `-1..=1` of an integer lattice point.
+fn lattice(seed: u32, x: i32, y: i32, z: i32) -> f32 {
+    let mut hash = seed.wrapping_mul(0x9E37_79B9)
+        ^ (x as u32).wrapping_mul(0x85EB_CA6B)
+        ^ (y as u32).wrapping_mul(0xC2B2_AE35)
+        ^ (z as u32).wrapping_mul(0x27D4_EB2F);
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0x2C1B_3C6D);
+    hash ^= hash >> 12;
+    hash = hash.wrapping_mul(0x297A_2D39);
+    hash ^= hash >> 15;
+    hash as f32 / u32::MAX as f32 * 2.0 - 1.0
+}
+
+/// Smoothly interpolated lattice noise within `-1..=1`.
+fn value_noise(seed: u32, p: Vector3<f32>) -> f32 {
+    let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+    let (x, y, z) = (p.x.floor(), p.y.floor(), p.z.floor());
+    let (fx, fy, fz) = (smooth(p.x - x), smooth(p.y - y), smooth(p.z - z));
+    let (x, y, z) = (x as i32, y as i32, z as i32);
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+
+    let plane = |z: i32| {
+        lerp(
+            lerp(lattice(seed, x, y, z), lattice(seed, x + 1, y, z), fx),
+            lerp(
+                lattice(seed, x, y + 1, z),
+                lattice(seed, x + 1, y + 1, z),
+                fx,
+            ),
+            fy,
+        )
+    };
+    lerp(plane(z), plane(z + 1), fz)
+}
+
+#[test]
+fn test_force_fields() {
+    let at =
+        |kind, position| ForceField { kind, pulse: None }.force_at(Vector3::from(position), 0.0);
+
+    let gravity = ForceKind::Uniform([0.0, -1.0, 0.0]);
+    assert_eq!(at(gravity, [5.0, 2.0, 1.0]), Vector3::new(0.0, -1.0, 0.0));
+
+    let radial = ForceKind::Radial {
+        center: [0.0, 0.0, 0.0],
+        strength: 2.0,
+        falloff: 1.0,
+    };
+    assert_eq!(at(radial, [3.0, 0.0, 0.0]), Vector3::new(-0.5, 0.0, 0.0));
+
+    let vortex = ForceKind::Vortex {
+        center: [0.0, 0.0, 0.0],
+        axis: [0.0, 1.0, 0.0],
+        strength: 1.0,
+        falloff: 0.0,
+    };
+    assert_eq!(at(vortex, [1.0, 7.0, 0.0]), Vector3::new(0.0, 0.0, -1.0));
+
+    let turbulence = ForceKind::Turbulence {
+        strength: 1.0,
+        scale: 10.0,
+        speed: 0.0,
+        seed: 4,
+    };
+    let near = at(turbulence, [3.0, 4.0, 5.0]) - at(turbulence, [3.1, 4.0, 5.0]);
+    assert!(near.magnitude() < 0.1);
+    assert!(at(turbulence, [3.0, 4.0, 5.0]).magnitude() <= 3f32.sqrt());
+
+    let pulsing = ForceField {
+        kind: gravity,
+        pulse: Some(Pulse {
+            period: 4.0,
+            amplitude: 0.5,
+            phase: 0.0,
+        }),
+    };
+    let y_at = |iteration| pulsing.force_at(Vector3::new(0.0, 0.0, 0.0), iteration).y;
+    assert_eq!(y_at(0.0), -1.0);
+    assert_eq!(y_at(1.0), -1.5);
+}
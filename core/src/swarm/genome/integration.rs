@@ -0,0 +1,102 @@
+use cgmath::Vector3;
+use serde::{Deserialize, Serialize};
+
+/// How agents move along their acceleration and velocity within a step.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Integration {
+    /// Movements per step, each over a time of `dt = 1 / substeps`. Speeds, accelerations,
+    /// movement energy and force pulses are measured per step, while replacement, fields and
+    /// terrain still update once per step, so more substeps only resolve the movement finer.
+    #[serde(default = "default_substeps")]
+    pub substeps: usize,
+    #[serde(default)]
+    pub integrator: Integrator,
+}
+
+fn default_substeps() -> usize {
+    1
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum Integrator {
+    /// Updates the velocity first and moves along the new velocity.
+    #[default]
+    SemiImplicitEuler,
+    /// Moves along the velocity and the current acceleration, and updates the velocity with
+    /// the mean of the acceleration of the previous and the current step.
+    VelocityVerlet,
+}
+
+impl Default for Integration {
+    fn default() -> Integration {
+        Integration {
+            substeps: default_substeps(),
+            integrator: Integrator::default(),
+        }
+    }
+}
+
+impl Integration {
+    /// Time covered by a substep, a step taking one unit of time.
+    pub fn dt(&self) -> f32 {
+        1.0 / self.substeps as f32
+    }
+
+    /// Velocity of an agent after the step and the offset to its new position. `previous` is
+    /// the acceleration of the agent in the last step, if it moved before. `limit` is applied
+    /// to every updated velocity, e.g. to clip it to the maximum speed.
+    pub fn advance(
+        &self,
+        velocity: Vector3<f32>,
+        previous: Option<Vector3<f32>>,
+        acceleration: Vector3<f32>,
+        limit: impl Fn(Vector3<f32>) -> Vector3<f32>,
+    ) -> (Vector3<f32>, Vector3<f32>) {
+        let dt = self.dt();
+        match self.integrator {
+            Integrator::SemiImplicitEuler => {
+                let new_velocity = limit(velocity + acceleration * dt);
+                (new_velocity, new_velocity * dt)
+            }
+            Integrator::VelocityVerlet => {
+                // The stored velocity was predicted with the previous acceleration only
+                let current = match previous {
+                    Some(previous) => limit(velocity + (acceleration - previous) * (0.5 * dt)),
+                    None => velocity,
+                };
+                let offset = current * dt + acceleration * (0.5 * dt * dt);
+                (limit(current + acceleration * dt), offset)
+            }
+        }
+    }
+}
+
+#[test]
+fn test_integrators_under_constant_acceleration() {
+    let gravity = Vector3::new(0.0, -1.0, 0.0);
+    let fall = |integration: Integration, steps: usize| {
+        let mut velocity = Vector3::new(0.0, 0.0, 0.0);
+        let (mut height, mut previous) = (0.0, None);
+        for _ in 0..steps {
+            let (v, offset) = integration.advance(velocity, previous, gravity, |v| v);
+            velocity = v;
+            previous = Some(gravity);
+            height += offset.y;
+        }
+        (height, velocity.y)
+    };
+
+    // Exactly -t^2 / 2 = -8 and -t = -4 after t = 4
+    let euler = Integration::default();
+    assert_eq!(fall(euler, 4), (-10.0, -4.0));
+    let finer = Integration {
+        substeps: 4,
+        ..euler
+    };
+    assert_eq!(fall(finer, 16), (-8.5, -4.0));
+    let verlet = Integration {
+        integrator: Integrator::VelocityVerlet,
+        ..euler
+    };
+    assert_eq!(fall(verlet, 4), (-8.0, -4.0));
+}
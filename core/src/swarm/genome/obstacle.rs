@@ -0,0 +1,540 @@
+use cgmath::{InnerSpace, Vector3};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Fixed geometry which agents avoid and can not enter, unless they are `noclip`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum ObstacleConfig {
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+    },
+    Box {
+        min: [f32; 3],
+        max: [f32; 3],
+    },
+    /// Everything behind the plane through `point`, with `normal` pointing out of the solid.
+    Plane {
+        point: [f32; 3],
+        normal: [f32; 3],
+    },
+    /// Closed Wavefront OBJ mesh with counter-clockwise faces seen from outside. Relative
+    /// paths in a config are resolved against the directory of the config.
+    Mesh {
+        path: PathBuf,
+        #[serde(default)]
+        offset: [f32; 3],
+        #[serde(default = "default_scale")]
+        scale: f32,
+    },
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+/// The geometry of an obstacle in world coordinates.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum Obstacle {
+    Sphere {
+        center: Vector3<f32>,
+        radius: f32,
+    },
+    Box {
+        min: Vector3<f32>,
+        max: Vector3<f32>,
+    },
+    Plane {
+        point: Vector3<f32>,
+        normal: Vector3<f32>,
+    },
+    Mesh {
+        triangles: Vec<[Vector3<f32>; 3]>,
+        min: Vector3<f32>,
+        max: Vector3<f32>,
+    },
+}
+
+impl Obstacle {
+    /// Obstacle of a sphere, box or plane config. Meshes have to be read with `from_obj`.
+    pub fn from_primitive(config: &ObstacleConfig) -> Result<Obstacle, String> {
+        match config {
+            ObstacleConfig::Sphere { center, radius } => Ok(Obstacle::Sphere {
+                center: Vector3::from(*center),
+                radius: *radius,
+            }),
+            ObstacleConfig::Box { min, max } => {
+                if !(0..3).all(|axis| min[axis] <= max[axis]) {
+                    return Err(format!(
+                        "Obstacle box needs a min below the max, but spans {:?} to {:?}",
+                        min, max
+                    ));
+                }
+                Ok(Obstacle::Box {
+                    min: Vector3::from(*min),
+                    max: Vector3::from(*max),
+                })
+            }
+            ObstacleConfig::Plane { point, normal } => {
+                let normal = Vector3::from(*normal);
+                if normal.magnitude2() == 0.0 {
+                    return Err("Obstacle plane needs a normal".to_string());
+                }
+                Ok(Obstacle::Plane {
+                    point: Vector3::from(*point),
+                    normal: normal.normalize(),
+                })
+            }
+            ObstacleConfig::Mesh { path, .. } => {
+                Err(format!("Obstacle mesh {} is no primitive", path.display()))
+            }
+        }
+    }
+
+    /// Parses the vertices and faces of a Wavefront OBJ. Polygons are split into triangle fans.
+    pub fn from_obj(text: &str, offset: Vector3<f32>, scale: f32) -> Result<Obstacle, String> {
+        let mut vertices: Vec<Vector3<f32>> = Vec::new();
+        let mut triangles = Vec::new();
+
+        for line in text.lines() {
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("v") => {
+                    let coords = tokens
+                        .take(3)
+                        .map(|token| {
+                            token
+                                .parse::<f32>()
+                                .map_err(|e| format!("Invalid vertex {}: {}", line, e))
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    if coords.len() != 3 {
+                        return Err(format!("Vertex needs three coordinates: {}", line));
+                    }
+                    vertices.push(offset + Vector3::new(coords[0], coords[1], coords[2]) * scale);
+                }
+                Some("f") => {
+                    let corners = tokens
+                        .map(|token| {
+                            let index = token
+                                .split('/')
+                                .next()
+                                .and_then(|index| index.parse::<i64>().ok());
+                            match index {
+                                Some(i) if i > 0 => vertices.get(i as usize - 1),
+                                Some(i) if i < 0 => vertices
+                                    .len()
+                                    .checked_sub(i.unsigned_abs() as usize)
+                                    .and_then(|i| vertices.get(i)),
+                                _ => None,
+                            }
+                            .copied()
+                            .ok_or_else(|| format!("Invalid face {}", line))
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    for i in 2..corners.len() {
+                        triangles.push([corners[0], corners[i - 1], corners[i]]);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        if triangles.is_empty() {
+            return Err("Obstacle mesh has no faces".to_string());
+        }
+        let corners = triangles.iter().flat_map(|triangle| triangle.iter());
+        let (min, max) = corners.fold(
+            (
+                Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+                Vector3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+            ),
+            |(min, max), c| {
+                (
+                    Vector3::new(min.x.min(c.x), min.y.min(c.y), min.z.min(c.z)),
+                    Vector3::new(max.x.max(c.x), max.y.max(c.y), max.z.max(c.z)),
+                )
+            },
+        );
+        Ok(Obstacle::Mesh {
+            triangles,
+            min,
+            max,
+        })
+    }
+
+    /// Signed distance from `position` to the surface, negative inside, and the outward normal
+    /// of the surface there. `None` if the surface is farther away than `range`.
+    pub fn nearest_surface(
+        &self,
+        position: Vector3<f32>,
+        range: f32,
+    ) -> Option<(f32, Vector3<f32>)> {
+        let (distance, normal) = match self {
+            Obstacle::Sphere { center, radius } => {
+                let diff = position - center;
+                let length = diff.magnitude();
+                let normal = if length > 0.0 {
+                    diff / length
+                } else {
+                    Vector3::unit_y()
+                };
+                (length - radius, normal)
+            }
+            Obstacle::Box { min, max } => box_distance(position, *min, *max),
+            Obstacle::Plane { point, normal } => ((position - point).dot(*normal), *normal),
+            Obstacle::Mesh {
+                triangles,
+                min,
+                max,
+            } => {
+                if box_distance(position, *min, *max).0 > range {
+                    return None;
+                }
+                mesh_distance(position, triangles)
+            }
+        };
+        if distance <= range {
+            Some((distance, normal))
+        } else {
+            None
+        }
+    }
+
+    /// Fraction of the way from `from` to `to` at which it first enters the obstacle, and the
+    /// outward normal of the surface there. `None` if the way does not enter the obstacle, e.g.
+    /// because it starts inside.
+    pub fn first_hit(&self, from: Vector3<f32>, to: Vector3<f32>) -> Option<(f32, Vector3<f32>)> {
+        let delta = to - from;
+        let hit = match self {
+            Obstacle::Sphere { center, radius } => {
+                let offset = from - center;
+                let a = delta.magnitude2();
+                let b = 2.0 * offset.dot(delta);
+                let c = offset.magnitude2() - radius * radius;
+                let discriminant = b * b - 4.0 * a * c;
+                if c < 0.0 || a == 0.0 || discriminant < 0.0 {
+                    return None;
+                }
+                let t = (-b - discriminant.sqrt()) / (2.0 * a);
+                let normal = (offset + delta * t) / *radius;
+                (t, normal)
+            }
+            Obstacle::Box { min, max } => {
+                let (enter, _, normal) = box_crossing(from, delta, *min, *max)?;
+                (enter, normal)
+            }
+            Obstacle::Plane { point, normal } => {
+                let (start, end) = ((from - point).dot(*normal), (to - point).dot(*normal));
+                if start < 0.0 || end >= 0.0 {
+                    return None;
+                }
+                (start / (start - end), *normal)
+            }
+            Obstacle::Mesh {
+                triangles,
+                min,
+                max,
+            } => {
+                let (enter, leave, _) = box_crossing(from, delta, *min, *max)?;
+                if enter > 1.0 || leave < 0.0 {
+                    return None;
+                }
+                triangles
+                    .iter()
+                    .filter_map(|triangle| triangle_hit(from, delta, triangle))
+                    .filter(|(t, _)| (0.0..=1.0).contains(t))
+                    .fold(
+                        None,
+                        |first: Option<(f32, Vector3<f32>)>, hit| match first {
+                            Some(first) if first.0 <= hit.0 => Some(first),
+                            _ => Some(hit),
+                        },
+                    )?
+            }
+        };
+        if (0.0..=1.0).contains(&hit.0) {
+            Some(hit)
+        } else {
+            None
+        }
+    }
+}
+
+/// Parameters at which `from + t * delta` enters and leaves the box, and the outward normal of
+/// the face it enters through. `None` if the line misses the box.
+fn box_crossing(
+    from: Vector3<f32>,
+    delta: Vector3<f32>,
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+) -> Option<(f32, f32, Vector3<f32>)> {
+    let (mut enter, mut leave) = (f32::NEG_INFINITY, f32::INFINITY);
+    let mut normal = Vector3::new(0.0, 0.0, 0.0);
+    for axis in 0..3 {
+        if delta[axis] == 0.0 {
+            if from[axis] < min[axis] || from[axis] > max[axis] {
+                return None;
+            }
+            continue;
+        }
+        let to_min = (min[axis] - from[axis]) / delta[axis];
+        let to_max = (max[axis] - from[axis]) / delta[axis];
+        let (near, far) = if to_min < to_max {
+            (to_min, to_max)
+        } else {
+            (to_max, to_min)
+        };
+        if near > enter {
+            enter = near;
+            normal = Vector3::new(0.0, 0.0, 0.0);
+            normal[axis] = -delta[axis].signum();
+        }
+        leave = leave.min(far);
+    }
+    if enter <= leave {
+        Some((enter, leave, normal))
+    } else {
+        None
+    }
+}
+
+/// Parameter at which `from + t * delta` passes the front of a triangle and its normal, after
+/// Möller and Trumbore.
+fn triangle_hit(
+    from: Vector3<f32>,
+    delta: Vector3<f32>,
+    [a, b, c]: &[Vector3<f32>; 3],
+) -> Option<(f32, Vector3<f32>)> {
+    let (ab, ac) = (b - a, c - a);
+    let face_normal = ab.cross(ac);
+    if face_normal.dot(delta) >= 0.0 {
+        return None;
+    }
+    let p = delta.cross(ac);
+    let inverse = 1.0 / ab.dot(p);
+    let ap = from - a;
+    let u = ap.dot(p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = ap.cross(ab);
+    let v = delta.dot(q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    Some((ac.dot(q) * inverse, face_normal.normalize()))
+}
+
+fn box_distance(
+    position: Vector3<f32>,
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+) -> (f32, Vector3<f32>) {
+    let clamped = Vector3::new(
+        position.x.clamp(min.x, max.x),
+        position.y.clamp(min.y, max.y),
+        position.z.clamp(min.z, max.z),
+    );
+    if clamped != position {
+        let diff = position - clamped;
+        return (diff.magnitude(), diff.normalize());
+    }
+
+    // Inside, leave through the nearest face
+    (0..3)
+        .flat_map(|axis| {
+            let mut normal = Vector3::new(0.0, 0.0, 0.0);
+            normal[axis] = 1.0;
+            vec![
+                (min[axis] - position[axis], -normal),
+                (position[axis] - max[axis], normal),
+            ]
+        })
+        .fold((f32::NEG_INFINITY, Vector3::unit_y()), |best, face| {
+            if face.0 > best.0 {
+                face
+            } else {
+                best
+            }
+        })
+}
+
+fn mesh_distance(position: Vector3<f32>, triangles: &[[Vector3<f32>; 3]]) -> (f32, Vector3<f32>) {
+    // Of equally close triangles, the one facing `position` the most decides the side
+    let mut best: Option<(f32, f32, Vector3<f32>, Vector3<f32>)> = None;
+    for triangle in triangles {
+        let face_normal = (triangle[1] - triangle[0]).cross(triangle[2] - triangle[0]);
+        if face_normal.magnitude2() == 0.0 {
+            continue;
+        }
+        let face_normal = face_normal.normalize();
+        let closest = closest_on_triangle(position, triangle);
+        let diff = position - closest;
+        let distance = diff.magnitude();
+        let facing = if distance > 0.0 {
+            diff.dot(face_normal) / distance
+        } else {
+            1.0
+        };
+
+        let closer = match best {
+            None => true,
+            Some((best_distance, best_facing, _, _)) => {
+                distance < best_distance - f32::EPSILON * best_distance.max(1.0)
+                    || (distance <= best_distance + f32::EPSILON * best_distance.max(1.0)
+                        && facing.abs() > best_facing.abs())
+            }
+        };
+        if closer {
+            best = Some((distance, facing, diff, face_normal));
+        }
+    }
+
+    match best {
+        Some((distance, facing, diff, _)) if distance > 0.0 => {
+            let sign = if facing < 0.0 { -1.0 } else { 1.0 };
+            (sign * distance, sign * diff / distance)
+        }
+        Some((_, _, _, face_normal)) => (0.0, face_normal),
+        None => (f32::INFINITY, Vector3::unit_y()),
+    }
+}
+
+/// Closest point to `p` on a triangle, after Ericson's Real-Time Collision Detection.
+fn closest_on_triangle(p: Vector3<f32>, [a, b, c]: &[Vector3<f32>; 3]) -> Vector3<f32> {
+    let (ab, ac) = (b - a, c - a);
+    let ap = p - a;
+    let (d1, d2) = (ab.dot(ap), ac.dot(ap));
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return *a;
+    }
+
+    let bp = p - b;
+    let (d3, d4) = (ab.dot(bp), ac.dot(bp));
+    if d3 >= 0.0 && d4 <= d3 {
+        return *b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = p - c;
+    let (d5, d6) = (ab.dot(cp), ac.dot(cp));
+    if d6 >= 0.0 && d5 <= d6 {
+        return *c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let denom = 1.0 / (va + vb + vc);
+    a + ab * (vb * denom) + ac * (vc * denom)
+}
+
+#[test]
+fn test_obstacle_distances() {
+    let cube_obj = "v -1 -1 -1\nv 1 -1 -1\nv 1 1 -1\nv -1 1 -1\n\
+                    v -1 -1 1\nv 1 -1 1\nv 1 1 1\nv -1 1 1\n\
+                    f 1 4 3 2\nf 5 6 7 8\nf 1 2 6 5\nf 4 8 7 3\nf 1 5 8 4\nf 2 3 7 6\n";
+    let offset = Vector3::new(10.0, 0.0, 0.0);
+    let mesh = Obstacle::from_obj(cube_obj, offset, 2.0).unwrap();
+    let cube = Obstacle::from_primitive(&ObstacleConfig::Box {
+        min: [8.0, -2.0, -2.0],
+        max: [12.0, 2.0, 2.0],
+    })
+    .unwrap();
+
+    let points = [
+        Vector3::new(10.0, 5.0, 0.0),
+        Vector3::new(10.5, 1.0, -0.5),
+        Vector3::new(15.0, 3.0, 0.0),
+        Vector3::new(9.2, 0.5, 1.0),
+    ];
+    for point in points.iter() {
+        let (mesh_dist, mesh_normal) = mesh.nearest_surface(*point, 100.0).unwrap();
+        let (cube_dist, cube_normal) = cube.nearest_surface(*point, 100.0).unwrap();
+        assert!((mesh_dist - cube_dist).abs() < 1e-5, "{:?}", point);
+        assert!(
+            (mesh_normal - cube_normal).magnitude() < 1e-5,
+            "{:?}",
+            point
+        );
+    }
+    assert_eq!(
+        mesh.nearest_surface(Vector3::new(10.0, 5.0, 0.0), 1.0),
+        None
+    );
+
+    let sphere = Obstacle::Sphere {
+        center: offset,
+        radius: 2.0,
+    };
+    assert_eq!(
+        sphere.nearest_surface(Vector3::new(10.0, 0.5, 0.0), 0.0),
+        Some((-1.5, Vector3::unit_y()))
+    );
+    let plane = Obstacle::from_primitive(&ObstacleConfig::Plane {
+        point: [0.0, 1.0, 0.0],
+        normal: [0.0, 2.0, 0.0],
+    })
+    .unwrap();
+    assert_eq!(
+        plane.nearest_surface(Vector3::new(3.0, 4.0, 5.0), 10.0),
+        Some((3.0, Vector3::unit_y()))
+    );
+}
+
+#[test]
+fn test_first_hit_on_the_way() {
+    let cube_obj = "v -1 -1 -1\nv 1 -1 -1\nv 1 1 -1\nv -1 1 -1\n\
+                    v -1 -1 1\nv 1 -1 1\nv 1 1 1\nv -1 1 1\n\
+                    f 1 4 3 2\nf 5 6 7 8\nf 1 2 6 5\nf 4 8 7 3\nf 1 5 8 4\nf 2 3 7 6\n";
+    // Off the axis, so the way does not run along the diagonals of the mesh faces
+    let (from, to) = (
+        Vector3::new(-5.0, 0.03, 0.04),
+        Vector3::new(5.0, 0.03, 0.04),
+    );
+    let obstacles = [
+        Obstacle::from_obj(cube_obj, Vector3::new(0.0, 0.0, 0.0), 0.1).unwrap(),
+        Obstacle::from_primitive(&ObstacleConfig::Box {
+            min: [-0.1, -5.0, -5.0],
+            max: [0.1, 5.0, 5.0],
+        })
+        .unwrap(),
+        Obstacle::Sphere {
+            center: Vector3::new(0.0, 0.03, 0.04),
+            radius: 0.1,
+        },
+        Obstacle::from_primitive(&ObstacleConfig::Plane {
+            point: [-0.1, 0.0, 0.0],
+            normal: [-1.0, 0.0, 0.0],
+        })
+        .unwrap(),
+    ];
+
+    for obstacle in obstacles.iter() {
+        // Thin enough to lie between two positions of a fast agent
+        let (t, normal) = obstacle.first_hit(from, to).unwrap();
+        assert!((t - 0.49).abs() < 1e-5, "{:?}", obstacle);
+        assert!(
+            (normal + Vector3::unit_x()).magnitude() < 1e-5,
+            "{:?}",
+            obstacle
+        );
+
+        assert_eq!(obstacle.first_hit(from, from - Vector3::unit_x()), None);
+        assert_eq!(obstacle.first_hit(from, from + Vector3::unit_x()), None);
+        // Ways starting inside are left to `nearest_surface`
+        assert_eq!(obstacle.first_hit(Vector3::new(0.0, 0.03, 0.04), to), None);
+    }
+}
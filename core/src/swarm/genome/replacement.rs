@@ -1,6 +1,8 @@
 use super::{Factor, Species, SpeciesIndex, SurroundingIndex, SwarmGenome};
 use crate::swarm::actor::{Agent, Artifact};
-use crate::utils::UidGen;
+use crate::utils::{random_one, safe_normalize, UidGen};
+use cgmath::{Deg, Euler, InnerSpace, Matrix, Matrix3, SquareMatrix, Vector3, Zero};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -11,10 +13,124 @@ pub struct ContextRule {
     pub weight: Factor,
     pub persist: bool,
     pub replacement: Replacement,
+    /// Further conditions on the context, which all have to hold.
+    pub conditions: Vec<ContextCondition>,
+    /// Conditions on the state of the agent, which all have to hold.
+    pub guards: Vec<StateGuard>,
+}
+
+/// Condition on the state of the agent a rule is applied to.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum StateGuard {
+    Energy(Interval),
+    /// Holds if the generation of the agent, counting the replacements since the start
+    /// distribution, leaves `remainder` when divided by `modulo`.
+    Generation {
+        modulo: usize,
+        remainder: usize,
+    },
+    /// Height of the agent above the terrain.
+    Height(Interval),
+    Speed(Interval),
+}
+
+/// Range of values, unbounded on the sides which are not set.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub struct Interval {
+    #[serde(default)]
+    pub min: Option<f32>,
+    #[serde(default)]
+    pub max: Option<f32>,
+}
+
+impl Interval {
+    pub fn contains(&self, value: f32) -> bool {
+        self.min.iter().all(|min| *min <= value) && self.max.iter().all(|max| value <= *max)
+    }
+}
+
+impl StateGuard {
+    /// `height` is the height of the agent above the terrain.
+    pub fn holds(&self, agent: &Agent, height: f32) -> bool {
+        match self {
+            StateGuard::Energy(interval) => interval.contains(agent.energy),
+            StateGuard::Generation { modulo, remainder } => {
+                *modulo != 0 && agent.iteration % modulo == *remainder
+            }
+            StateGuard::Height(interval) => interval.contains(height),
+            StateGuard::Speed(interval) => interval.contains(agent.velocity.magnitude()),
+        }
+    }
+}
+
+/// Condition on the actors around an agent, including the agent itself.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum ContextCondition {
+    /// At least `count` actors of kind `of` are within `range`, or the range of the rule.
+    AtLeast {
+        of: SurroundingIndex,
+        count: usize,
+        range: Option<f32>,
+    },
+    /// At most `count` actors of kind `of` are within `range`, or the range of the rule.
+    AtMost {
+        of: SurroundingIndex,
+        count: usize,
+        range: Option<f32>,
+    },
+    All(Vec<ContextCondition>),
+    Any(Vec<ContextCondition>),
+    Not(Box<ContextCondition>),
+}
+
+impl ContextCondition {
+    pub fn holds(&self, context: &[(f32, SurroundingIndex)], rule_range: f32) -> bool {
+        let count_within = |of: &SurroundingIndex, range: &Option<f32>| {
+            let range = range.unwrap_or(rule_range);
+            context
+                .iter()
+                .filter(|(dist, surrounding)| *dist < range && surrounding == of)
+                .count()
+        };
+
+        match self {
+            ContextCondition::AtLeast { of, count, range } => count_within(of, range) >= *count,
+            ContextCondition::AtMost { of, count, range } => count_within(of, range) <= *count,
+            ContextCondition::All(conditions) => conditions
+                .iter()
+                .all(|condition| condition.holds(context, rule_range)),
+            ContextCondition::Any(conditions) => conditions
+                .iter()
+                .any(|condition| condition.holds(context, rule_range)),
+            ContextCondition::Not(condition) => !condition.holds(context, rule_range),
+        }
+    }
+
+    /// Largest range the condition looks at, if it overrides the range of the rule.
+    pub fn max_range(&self) -> Option<f32> {
+        match self {
+            ContextCondition::AtLeast { range, .. } | ContextCondition::AtMost { range, .. } => {
+                *range
+            }
+            ContextCondition::All(conditions) | ContextCondition::Any(conditions) => conditions
+                .iter()
+                .filter_map(ContextCondition::max_range)
+                .fold(None, |max, range| Some(range.max(max.unwrap_or(range)))),
+            ContextCondition::Not(condition) => condition.max_range(),
+        }
+    }
 }
 
 impl ContextRule {
     pub fn is_applicable(&self, context: &Vec<(f32, SurroundingIndex)>) -> bool {
+        if !self
+            .conditions
+            .iter()
+            .all(|condition| condition.holds(context, self.range))
+        {
+            return false;
+        }
+
         if self.context.is_empty() {
             return true;
         }
@@ -31,17 +147,35 @@ impl ContextRule {
         checkset.is_empty()
     }
 
+    /// Whether all guards hold for `agent` at `height` above the terrain.
+    pub fn is_allowed_for(&self, agent: &Agent, height: f32) -> bool {
+        self.guards.iter().all(|guard| guard.holds(agent, height))
+    }
+
+    /// Range within which the context has to be known to check the rule.
+    pub fn max_range(&self) -> f32 {
+        self.conditions
+            .iter()
+            .filter_map(ContextCondition::max_range)
+            .fold(self.range, f32::max)
+    }
+
     pub fn replace_agent(
         &self,
         parent: &Agent,
         genome: &SwarmGenome,
         uid_gen: &mut UidGen,
+        rnd: &mut impl Rng,
     ) -> (Vec<Agent>, Vec<Artifact>) {
-        let (ags, arts) = self
-            .replacement
-            .replace_agent(parent, genome, uid_gen, self.persist);
+        let (ags, arts) =
+            self.replacement
+                .replace_agent(parent, genome, uid_gen, self.persist, rnd);
         (ags, arts)
     }
+
+    pub fn required_uids(&self, parent: &Agent, genome: &SwarmGenome) -> usize {
+        self.replacement.required_uids(parent, genome)
+    }
 }
 
 impl Default for ContextRule {
@@ -52,16 +186,152 @@ impl Default for ContextRule {
             persist: false,
             replacement: Replacement::None,
             range: 5.0,
+            conditions: Vec::new(),
+            guards: Vec::new(),
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum Replacement {
     None,
     Simple(Vec<SurroundingIndex>),
     Multi(Vec<Replacement>),
     Spread(SpeciesIndex, usize, usize),
+    /// Sends `count` agents of a species off in the directions of a pattern, at the speed of
+    /// the parent or at unit speed if the parent does not move.
+    Pattern(SpeciesIndex, usize, SpreadPattern),
+    /// Moves the agents of a replacement and changes their velocity.
+    Transformed(Box<Replacement>, OffspringTransform),
+}
+
+/// Directions offspring are spread in, relative to the heading of the parent. Parents that
+/// do not move use the world z axis as heading.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum SpreadPattern {
+    /// Evenly around the heading, each at `angle` degrees to it.
+    Cone { angle: f32 },
+    /// Evenly over all directions along a Fibonacci spiral.
+    Sphere,
+    /// Turns by the golden angle around the heading from one agent to the next, while the
+    /// angle to the heading grows up to `angle` degrees like the seeds of a sunflower.
+    Phyllotaxis { angle: f32 },
+}
+
+impl SpreadPattern {
+    /// Unit directions of `count` agents, with the heading along the z axis.
+    pub fn directions(&self, count: usize) -> Vec<Vector3<f32>> {
+        let golden_angle = std::f32::consts::PI * (3.0 - 5f32.sqrt());
+        let around = |polar: f32, azimuth: f32| {
+            Vector3::new(
+                polar.sin() * azimuth.cos(),
+                polar.sin() * azimuth.sin(),
+                polar.cos(),
+            )
+        };
+
+        (0..count)
+            .map(|i| {
+                let i = i as f32;
+                let n = count as f32;
+                match self {
+                    SpreadPattern::Cone { angle } => {
+                        around(angle.to_radians(), i / n * 2.0 * std::f32::consts::PI)
+                    }
+                    SpreadPattern::Sphere => {
+                        around((1.0 - (2.0 * i + 1.0) / n).acos(), i * golden_angle)
+                    }
+                    SpreadPattern::Phyllotaxis { angle } => around(
+                        angle.to_radians() * ((i + 1.0) / n).sqrt(),
+                        i * golden_angle,
+                    ),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Rotation from a frame with the z axis along `velocity`, the y axis as far up as possible
+/// and the x axis to the left into the world.
+fn heading_basis(velocity: Vector3<f32>) -> Matrix3<f32> {
+    let forward = safe_normalize(velocity);
+    if forward.is_zero() {
+        return Matrix3::identity();
+    }
+    let mut left = Vector3::unit_y().cross(forward);
+    if left.is_zero() {
+        left = Vector3::unit_x();
+    }
+    let left = left.normalize();
+    Matrix3::from_cols(left, forward.cross(left), forward)
+}
+
+/// Frame the offset and rotation of an `OffspringTransform` are given in.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Frame {
+    /// Axes of the world.
+    World,
+    /// The z axis points along the velocity of the parent, the y axis as far up as possible
+    /// and the x axis to the left, keeping the frame right-handed. Falls back to the world
+    /// frame if the parent does not move.
+    #[default]
+    Heading,
+}
+
+/// Places offspring relative to their parent and turns, scales and randomizes their velocity,
+/// in this order.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct OffspringTransform {
+    pub frame: Frame,
+    /// Offset of the offspring to the parent. In the heading frame, +x is to the left of the
+    /// parent, +y above and +z ahead.
+    pub offset: [f32; 3],
+    /// Euler angles in degrees the velocity is rotated by around the x, y and z axis,
+    /// counter-clockwise when looking against the axis. In the heading frame, a positive
+    /// angle around y turns left and a positive angle around x turns down.
+    pub rotation: [f32; 3],
+    /// Factor on the velocity.
+    pub speed: f32,
+    /// Largest random change of the velocity on every axis.
+    pub jitter: f32,
+}
+
+impl Default for OffspringTransform {
+    fn default() -> OffspringTransform {
+        OffspringTransform {
+            frame: Frame::default(),
+            offset: [0.0, 0.0, 0.0],
+            rotation: [0.0, 0.0, 0.0],
+            speed: 1.0,
+            jitter: 0.0,
+        }
+    }
+}
+
+impl OffspringTransform {
+    /// Rotation from the frame of the transform into the world.
+    fn basis(&self, parent: &Agent) -> Matrix3<f32> {
+        match self.frame {
+            Frame::World => Matrix3::identity(),
+            Frame::Heading => heading_basis(parent.velocity),
+        }
+    }
+
+    pub fn apply(&self, parent: &Agent, offspring: &mut Agent, rnd: &mut impl Rng) {
+        let basis = self.basis(parent);
+        let rotation = Matrix3::from(Euler {
+            x: Deg(self.rotation[0]),
+            y: Deg(self.rotation[1]),
+            z: Deg(self.rotation[2]),
+        });
+
+        offspring.position = parent.position + basis * Vector3::from(self.offset);
+        offspring.velocity = basis * rotation * basis.transpose() * offspring.velocity * self.speed;
+        if self.jitter != 0.0 {
+            offspring.velocity += random_one(rnd) * self.jitter;
+        }
+    }
 }
 
 impl Replacement {
@@ -71,7 +341,20 @@ impl Replacement {
             Replacement::Simple(new_indices) => new_indices.len(),
             Replacement::Multi(repls) => repls.iter().map(|o| o.count_replacements()).sum(),
             Replacement::Spread(_, count, _) => *count,
+            Replacement::Pattern(_, count, _) => *count,
+            Replacement::Transformed(replacement, _) => replacement.count_replacements(),
+        }
+    }
+
+    /// Number of uids `replace_agent` draws when replacing `parent`.
+    pub fn required_uids(&self, parent: &Agent, genome: &SwarmGenome) -> usize {
+        let parent_species = &genome.species_map[parent.species_index.0];
+
+        if !parent_species.energy.on_zero.is_alive(parent.energy) {
+            return parent_species.energy.on_zero.required_uids();
         }
+
+        self.count_replacements()
     }
 
     fn generate_agent(
@@ -99,6 +382,7 @@ impl Replacement {
         parent_species: &Species,
         energy: f32,
         uid_gen: &mut UidGen,
+        rnd: &mut impl Rng,
     ) -> (Vec<Agent>, Vec<Artifact>) {
         assert!(energy.is_finite(), "Not finite! Was {:?}", energy);
         let mut new_agents: Vec<Agent> = vec![];
@@ -136,7 +420,7 @@ impl Replacement {
             Replacement::Multi(repls) => {
                 for repl in repls.iter() {
                     let (mut ags, mut arts) =
-                        repl.replace_agent_unchecked(parent, parent_species, energy, uid_gen);
+                        repl.replace_agent_unchecked(parent, parent_species, energy, uid_gen, rnd);
                     new_agents.append(&mut ags);
                     new_artifacts.append(&mut arts);
                 }
@@ -170,6 +454,39 @@ impl Replacement {
                     new_vel = rot * new_vel;
                 }
             }
+            Replacement::Pattern(new_species_index, count, pattern) => {
+                let basis = heading_basis(parent.velocity);
+                let speed = match parent.velocity.magnitude() {
+                    speed if speed > 0.0 => speed,
+                    _ => 1.0,
+                };
+
+                for direction in pattern.directions(*count) {
+                    let mut new_agent = Self::generate_agent(
+                        parent,
+                        *new_species_index,
+                        energy,
+                        parent_species.hand_down_seed,
+                        uid_gen,
+                    );
+                    new_agent.velocity = basis * direction * speed;
+                    new_agents.push(new_agent);
+                }
+            }
+            Replacement::Transformed(replacement, transform) => {
+                let (mut ags, mut arts) = replacement.replace_agent_unchecked(
+                    parent,
+                    parent_species,
+                    energy,
+                    uid_gen,
+                    rnd,
+                );
+                for agent in ags.iter_mut() {
+                    transform.apply(parent, agent, rnd);
+                }
+                new_agents.append(&mut ags);
+                new_artifacts.append(&mut arts);
+            }
         };
         (new_agents, new_artifacts)
     }
@@ -180,6 +497,7 @@ impl Replacement {
         genome: &SwarmGenome,
         uid_gen: &mut UidGen,
         persist: bool,
+        rnd: &mut impl Rng,
     ) -> (Vec<Agent>, Vec<Artifact>) {
         let parent_species = &genome.species_map[parent.species_index.0];
 
@@ -187,7 +505,7 @@ impl Replacement {
             return parent_species
                 .energy
                 .on_zero
-                .replacement(parent, parent_species, uid_gen);
+                .replacement(parent, parent_species, uid_gen, rnd);
         }
 
         let per_offspring_energy = parent_species.energy.for_offspring.get(
@@ -201,8 +519,13 @@ impl Replacement {
             per_offspring_energy,
         );
 
-        let (mut new_agents, new_artifacts) =
-            self.replace_agent_unchecked(parent, parent_species, per_offspring_energy, uid_gen);
+        let (mut new_agents, new_artifacts) = self.replace_agent_unchecked(
+            parent,
+            parent_species,
+            per_offspring_energy,
+            uid_gen,
+            rnd,
+        );
 
         if persist {
             let mut new_parent = parent.clone();
@@ -235,6 +558,9 @@ impl Default for Replacement {
 pub struct ApplicationStrategy {
     pub every: usize,
     pub offset: usize,
+    /// Chance of every agent to be replaced when the strategy fires.
+    #[serde(default = "crate::utils::one")]
+    pub probability: f32,
 }
 
 impl ApplicationStrategy {
@@ -255,6 +581,196 @@ impl From<super::dummies::DummyApplicationStrategy> for ApplicationStrategy {
         ApplicationStrategy {
             every: dummy.every,
             offset: dummy.offset.unwrap_or_else(|| dummy.every),
+            probability: dummy.probability,
         }
     }
 }
+
+#[test]
+fn test_context_conditions() {
+    use super::ArtifactIndex;
+
+    let trunk = SurroundingIndex::Agent(SpeciesIndex(0));
+    let leaf = SurroundingIndex::Artifact(ArtifactIndex(0));
+    let context = vec![(1.0, trunk), (2.0, trunk), (4.0, trunk), (8.0, leaf)];
+
+    let at_least = |count, range| ContextCondition::AtLeast {
+        of: trunk,
+        count,
+        range,
+    };
+    let absent_leaf = |range| ContextCondition::AtMost {
+        of: leaf,
+        count: 0,
+        range,
+    };
+    assert!(at_least(3, None).holds(&context, 5.0));
+    assert!(!at_least(3, Some(3.0)).holds(&context, 5.0));
+    assert!(absent_leaf(None).holds(&context, 5.0));
+    assert!(!absent_leaf(Some(10.0)).holds(&context, 5.0));
+
+    let crowded = ContextRule {
+        conditions: vec![
+            ContextCondition::Any(vec![at_least(4, None), absent_leaf(Some(10.0))]),
+            ContextCondition::Not(Box::new(at_least(2, Some(1.5)))),
+        ],
+        ..Default::default()
+    };
+    assert_eq!(crowded.max_range(), 10.0);
+    assert!(!crowded.is_applicable(&context));
+    assert!(crowded.is_applicable(&context[..3].to_vec()));
+}
+
+#[test]
+fn test_state_guards() {
+    let mut agent = Agent::mk_new(
+        cgmath::Vector3::new(0.0, 30.0, 0.0),
+        cgmath::Vector3::new(3.0, 4.0, 0.0),
+        10.0,
+        SpeciesIndex(0),
+        cgmath::Vector3::new(0.0, 0.0, 0.0),
+        4,
+        UidGen::default().next(),
+    )
+    .unwrap();
+    let above = |min| Interval {
+        min: Some(min),
+        max: None,
+    };
+
+    let rule = ContextRule {
+        guards: vec![
+            StateGuard::Height(above(20.0)),
+            StateGuard::Energy(above(5.0)),
+            StateGuard::Speed(Interval {
+                min: Some(4.0),
+                max: Some(6.0),
+            }),
+            StateGuard::Generation {
+                modulo: 2,
+                remainder: 0,
+            },
+        ],
+        ..Default::default()
+    };
+    assert!(rule.is_allowed_for(&agent, 25.0));
+    assert!(!rule.is_allowed_for(&agent, 15.0));
+
+    agent.iteration += 1;
+    assert!(!rule.is_allowed_for(&agent, 25.0));
+}
+
+#[test]
+fn test_offspring_transform() {
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    let parent = Agent::mk_new(
+        Vector3::new(1.0, 2.0, 3.0),
+        Vector3::new(2.0, 0.0, 0.0),
+        10.0,
+        SpeciesIndex(0),
+        Vector3::new(0.0, 0.0, 0.0),
+        0,
+        UidGen::default().next(),
+    )
+    .unwrap();
+    let mut rnd = SmallRng::seed_from_u64(0);
+    let transformed = |transform: OffspringTransform, rnd: &mut SmallRng| {
+        let mut offspring = parent.clone();
+        transform.apply(&parent, &mut offspring, rnd);
+        offspring
+    };
+    let close = |a: Vector3<f32>, b: Vector3<f32>| (a - b).magnitude() < 1e-5;
+
+    // Heading along +x with y up, so left is -z
+    let aside = OffspringTransform {
+        offset: [1.0, 0.0, 0.0],
+        ..OffspringTransform::default()
+    };
+    assert!(close(
+        transformed(aside, &mut rnd).position,
+        Vector3::new(1.0, 2.0, 2.0)
+    ));
+
+    // One ahead of the parent, turning left by a quarter at half the speed
+    let branch = OffspringTransform {
+        offset: [0.0, 0.0, 1.0],
+        rotation: [0.0, 90.0, 0.0],
+        speed: 0.5,
+        ..OffspringTransform::default()
+    };
+    let offspring = transformed(branch, &mut rnd);
+    assert!(close(offspring.position, Vector3::new(2.0, 2.0, 3.0)));
+    assert!(close(offspring.velocity, Vector3::new(0.0, 0.0, -1.0)));
+
+    let world = OffspringTransform {
+        frame: Frame::World,
+        offset: [0.0, 0.0, 1.0],
+        jitter: 0.5,
+        ..OffspringTransform::default()
+    };
+    let offspring = transformed(world, &mut rnd);
+    assert!(close(offspring.position, Vector3::new(1.0, 2.0, 4.0)));
+    let change = offspring.velocity - parent.velocity;
+    assert!(change.x.abs() <= 0.5 && change.y.abs() <= 0.5 && change.z.abs() <= 0.5);
+    assert!(!change.is_zero());
+
+    let replacement =
+        Replacement::Transformed(Box::new(Replacement::Spread(SpeciesIndex(0), 3, 0)), branch);
+    assert_eq!(replacement.count_replacements(), 3);
+}
+
+#[test]
+fn test_spread_patterns() {
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    let heading = Vector3::unit_z();
+    let angle_to_heading = |direction: &Vector3<f32>| direction.angle(heading).0.to_degrees();
+
+    let cone = SpreadPattern::Cone { angle: 30.0 }.directions(5);
+    assert_eq!(cone.len(), 5);
+    assert!(cone
+        .iter()
+        .all(|direction| (angle_to_heading(direction) - 30.0).abs() < 1e-3));
+    assert!(cone.iter().sum::<Vector3<f32>>().truncate().magnitude() < 1e-5);
+
+    let sphere = SpreadPattern::Sphere.directions(100);
+    assert!(sphere
+        .iter()
+        .all(|direction| (direction.magnitude() - 1.0).abs() < 1e-5));
+    assert!(sphere.iter().sum::<Vector3<f32>>().magnitude() < 1.0);
+
+    let sunflower = SpreadPattern::Phyllotaxis { angle: 60.0 }.directions(8);
+    let angles: Vec<f32> = sunflower.iter().map(angle_to_heading).collect();
+    assert!(angles.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!((angles[7] - 60.0).abs() < 1e-3);
+
+    // Oriented along the velocity of the parent
+    let basis = heading_basis(Vector3::new(2.0, 0.0, 0.0));
+    assert!((basis * heading - Vector3::unit_x()).magnitude() < 1e-5);
+
+    // Stationary parents send their offspring off at unit speed along the world z axis
+    let genome = crate::utils::sample_genome("context_config.json");
+    let parent = Agent::mk_new(
+        Vector3::zero(),
+        Vector3::zero(),
+        10.0,
+        SpeciesIndex(0),
+        Vector3::zero(),
+        0,
+        UidGen::default().next(),
+    )
+    .unwrap();
+    let pattern = Replacement::Pattern(SpeciesIndex(0), 3, SpreadPattern::Cone { angle: 0.0 });
+    let (offspring, _) = pattern.replace_agent_unchecked(
+        &parent,
+        &genome.species_map[0],
+        1.0,
+        &mut UidGen::default(),
+        &mut SmallRng::seed_from_u64(0),
+    );
+    assert_eq!(offspring.len(), 3);
+    assert!(offspring
+        .iter()
+        .all(|agent| (agent.velocity - heading).magnitude() < 1e-5));
+}
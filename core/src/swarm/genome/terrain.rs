@@ -0,0 +1,373 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// How the buoys of the terrain gather the influence of the actors.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum TerrainUpdate {
+    /// Every buoy is influenced by every actor.
+    #[default]
+    Exact,
+    /// Every buoy is only influenced by actors whose influence `1 / (1 + distance)^factor`
+    /// is at least the given tolerance. Buoys out of reach of every actor keep their height.
+    CutOff(f32),
+}
+
+impl TerrainUpdate {
+    /// Horizontal distance beyond which an actor with the given influence factor is ignored.
+    pub fn cut_off_radius(&self, factor: f32) -> f32 {
+        match self {
+            TerrainUpdate::CutOff(tolerance) if factor > 0.0 && *tolerance > 0.0 => {
+                (tolerance.powf(-1.0 / factor) - 1.0).max(0.0)
+            }
+            _ => f32::INFINITY,
+        }
+    }
+}
+
+/// How a terrain influence moves the buoys it reaches.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum InfluenceMode {
+    /// Buoys are pulled toward the height of the actor.
+    #[default]
+    Pull,
+    /// Buoys above the actor are pulled down to it, buoys below are left alone.
+    Carve,
+    /// Buoys below the actor are pulled up to it, buoys above are left alone.
+    Deposit,
+    /// Buoys are pulled toward the given height, wherever the actor is.
+    Level(f32),
+}
+
+impl InfluenceMode {
+    /// Height difference by which an actor at `actor` pulls a buoy at `buoy`, `None` if the
+    /// buoy is left alone.
+    pub fn pull(&self, actor: f32, buoy: f32) -> Option<f32> {
+        match self {
+            InfluenceMode::Pull => Some(actor - buoy),
+            InfluenceMode::Carve => Some(actor - buoy).filter(|diff| *diff < 0.0),
+            InfluenceMode::Deposit => Some(actor - buoy).filter(|diff| *diff > 0.0),
+            InfluenceMode::Level(level) => Some(level - buoy),
+        }
+    }
+}
+
+/// How the weight of a terrain influence decreases with the horizontal distance. Negative
+/// weights push buoys away instead of pulling them.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum Falloff {
+    /// `1 / (1 + distance)^factor`.
+    #[default]
+    InversePower,
+    /// `factor * exp(-distance^2 / (2 * sigma^2))` with the given sigma.
+    Gaussian(f32),
+    /// `factor * (1 - distance / radius)` with the given radius, zero beyond it.
+    Linear(f32),
+}
+
+impl Falloff {
+    pub fn weight(&self, factor: f32, distance: f32) -> f32 {
+        match self {
+            Falloff::InversePower => 1.0 / (1.0 + distance).powf(factor),
+            Falloff::Gaussian(sigma) => factor * (-distance.powi(2) / (2.0 * sigma * sigma)).exp(),
+            Falloff::Linear(radius) => factor * (1.0 - distance / radius).max(0.0),
+        }
+    }
+
+    /// Horizontal distance beyond which an actor with the given factor is ignored.
+    pub fn reach(&self, factor: f32, update: TerrainUpdate) -> f32 {
+        match (self, update) {
+            (Falloff::InversePower, _) => update.cut_off_radius(factor),
+            (Falloff::Linear(radius), _) => radius.max(0.0),
+            (Falloff::Gaussian(sigma), TerrainUpdate::CutOff(tolerance)) if tolerance > 0.0 => {
+                sigma.abs() * (2.0 * (factor.abs() / tolerance).ln()).max(0.0).sqrt()
+            }
+            (Falloff::Gaussian(_), _) => f32::INFINITY,
+        }
+    }
+}
+
+/// Mode and falloff of the terrain influence of a species or artifact type.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub struct InfluenceBehavior {
+    #[serde(default)]
+    pub mode: InfluenceMode,
+    #[serde(default)]
+    pub falloff: Falloff,
+}
+
+/// Passes applied to the terrain after every terrain update, in field order. All are off by
+/// default.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub struct TerrainErosion {
+    #[serde(default)]
+    pub smoothing: Option<Smoothing>,
+    #[serde(default)]
+    pub thermal: Option<ThermalErosion>,
+    #[serde(default)]
+    pub hydraulic: Option<HydraulicErosion>,
+}
+
+impl TerrainErosion {
+    pub fn is_enabled(&self) -> bool {
+        self.smoothing.is_some() || self.thermal.is_some() || self.hydraulic.is_some()
+    }
+}
+
+/// Laplacian smoothing, moving every buoy toward the average of its neighbours.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Smoothing {
+    /// Fraction of the way to the neighbour average, within `0..=1`.
+    #[serde(default = "default_half")]
+    pub strength: f32,
+    #[serde(default = "default_iterations")]
+    pub iterations: usize,
+}
+
+/// Material slides down wherever the terrain is steeper than the talus slope.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct ThermalErosion {
+    /// Steepest stable slope as height per horizontal distance.
+    #[serde(default = "default_talus")]
+    pub talus: f32,
+    /// Fraction of the excess height moved per iteration, within `0..=1`.
+    #[serde(default = "default_half")]
+    pub rate: f32,
+    #[serde(default = "default_iterations")]
+    pub iterations: usize,
+}
+
+/// Rain dissolves material which is carried downhill by the water and deposited where the
+/// water evaporates. The water does not persist between steps.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct HydraulicErosion {
+    /// Water added to every buoy per iteration.
+    #[serde(default = "default_rain")]
+    pub rain: f32,
+    /// Material dissolved per unit of water.
+    #[serde(default = "default_solubility")]
+    pub solubility: f32,
+    /// Fraction of the water evaporating per iteration, within `0..=1`.
+    #[serde(default = "default_half")]
+    pub evaporation: f32,
+    /// Material a unit of water can carry.
+    #[serde(default = "default_solubility")]
+    pub capacity: f32,
+    #[serde(default = "default_hydraulic_iterations")]
+    pub iterations: usize,
+}
+
+fn default_half() -> f32 {
+    0.5
+}
+
+fn default_iterations() -> usize {
+    1
+}
+
+fn default_talus() -> f32 {
+    1.0
+}
+
+fn default_rain() -> f32 {
+    0.1
+}
+
+fn default_solubility() -> f32 {
+    0.01
+}
+
+fn default_hydraulic_iterations() -> usize {
+    10
+}
+
+/// Initial heights of the terrain, stretched over the whole buoy grid.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct HeightmapConfig {
+    /// PGM (P2 or P5) or CSV file. Relative paths in a config are resolved against the
+    /// directory of the config.
+    pub path: PathBuf,
+    /// Height of a sample with value 1. PGM samples are normalized to `0..=1`.
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    /// Height of a sample with value 0.
+    #[serde(default)]
+    pub offset: f32,
+    /// Weight with which every buoy is pulled back to its initial height, as if an actor with
+    /// an influence of `anchor` was placed at it. Buoys are free to move by default.
+    #[serde(default)]
+    pub anchor: f32,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+/// A heightmap config along with the heights read from its file.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TerrainHeightmap {
+    pub config: HeightmapConfig,
+    pub heightmap: Heightmap,
+}
+
+/// A grid of height samples, stored row by row. Rows run along x, columns along z.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Heightmap {
+    width: usize,
+    depth: usize,
+    samples: Vec<f32>,
+}
+
+impl Heightmap {
+    pub fn new(width: usize, depth: usize, samples: Vec<f32>) -> Result<Heightmap, String> {
+        if width == 0 || depth == 0 || samples.len() != width * depth {
+            return Err(format!(
+                "Heightmap of {}x{} can not hold {} samples",
+                width,
+                depth,
+                samples.len()
+            ));
+        }
+        Ok(Heightmap {
+            width,
+            depth,
+            samples,
+        })
+    }
+
+    /// Parses a plain (P2) or binary (P5) greymap and normalizes it to `0..=1`.
+    pub fn from_pgm(bytes: &[u8]) -> Result<Heightmap, String> {
+        let mut pos = 0;
+        let mut header = Vec::with_capacity(4);
+        while header.len() < 4 {
+            while pos < bytes.len() && (bytes[pos].is_ascii_whitespace() || bytes[pos] == b'#') {
+                if bytes[pos] == b'#' {
+                    while pos < bytes.len() && bytes[pos] != b'\n' {
+                        pos += 1;
+                    }
+                } else {
+                    pos += 1;
+                }
+            }
+            let start = pos;
+            while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                return Err("PGM header ended early".to_string());
+            }
+            header.push(String::from_utf8_lossy(&bytes[start..pos]).to_string());
+        }
+
+        let parse = |token: &str| {
+            token
+                .parse::<usize>()
+                .map_err(|e| format!("Invalid PGM header value {}: {}", token, e))
+        };
+        let (width, depth, max) = (parse(&header[1])?, parse(&header[2])?, parse(&header[3])?);
+        if max == 0 || max > 65535 {
+            return Err(format!("Invalid PGM maximum {}", max));
+        }
+
+        let samples: Vec<usize> = match header[0].as_str() {
+            "P2" => String::from_utf8_lossy(&bytes[pos..])
+                .split_ascii_whitespace()
+                .map(parse)
+                .collect::<Result<_, _>>()?,
+            "P5" => {
+                let data = bytes.get(pos + 1..).unwrap_or(&[]);
+                if max < 256 {
+                    data.iter().map(|b| *b as usize).collect()
+                } else {
+                    data.chunks_exact(2)
+                        .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+                        .collect()
+                }
+            }
+            magic => return Err(format!("Unsupported PGM type {}", magic)),
+        };
+
+        Heightmap::new(
+            width,
+            depth,
+            samples
+                .into_iter()
+                .take(width * depth)
+                .map(|s| s as f32 / max as f32)
+                .collect(),
+        )
+    }
+
+    /// Parses comma separated heights with one line per row.
+    pub fn from_csv(text: &str) -> Result<Heightmap, String> {
+        let rows: Vec<Vec<f32>> = text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                line.split(',')
+                    .map(|value| {
+                        value
+                            .trim()
+                            .parse::<f32>()
+                            .map_err(|e| format!("Invalid height {}: {}", value, e))
+                    })
+                    .collect()
+            })
+            .collect::<Result<_, _>>()?;
+
+        let width = rows.first().map_or(0, Vec::len);
+        if rows.iter().any(|row| row.len() != width) {
+            return Err("Rows of the heightmap differ in length".to_string());
+        }
+        Heightmap::new(width, rows.len(), rows.concat())
+    }
+
+    /// Bilinearly interpolated sample at the relative position `u` along x and `v` along z,
+    /// both within `0..=1`.
+    pub fn sample(&self, u: f32, v: f32) -> f32 {
+        let x = u.clamp(0.0, 1.0) * (self.width - 1) as f32;
+        let z = v.clamp(0.0, 1.0) * (self.depth - 1) as f32;
+        let (x0, z0) = (x.floor() as usize, z.floor() as usize);
+        let (x1, z1) = ((x0 + 1).min(self.width - 1), (z0 + 1).min(self.depth - 1));
+        let at = |x: usize, z: usize| self.samples[x + z * self.width];
+
+        let near = at(x0, z0) * (1.0 - x.fract()) + at(x1, z0) * x.fract();
+        let far = at(x0, z1) * (1.0 - x.fract()) + at(x1, z1) * x.fract();
+        near * (1.0 - z.fract()) + far * z.fract()
+    }
+}
+
+#[test]
+fn test_influence_behavior() {
+    assert_eq!(InfluenceMode::Pull.pull(1.0, 3.0), Some(-2.0));
+    assert_eq!(InfluenceMode::Carve.pull(1.0, 3.0), Some(-2.0));
+    assert_eq!(InfluenceMode::Carve.pull(3.0, 1.0), None);
+    assert_eq!(InfluenceMode::Deposit.pull(1.0, 3.0), None);
+    assert_eq!(InfluenceMode::Deposit.pull(3.0, 1.0), Some(2.0));
+    assert_eq!(InfluenceMode::Level(5.0).pull(-10.0, 3.0), Some(2.0));
+
+    let update = TerrainUpdate::CutOff(0.01);
+    for falloff in &[
+        Falloff::InversePower,
+        Falloff::Gaussian(4.0),
+        Falloff::Linear(8.0),
+    ] {
+        let reach = falloff.reach(2.0, update);
+        assert!(falloff.weight(2.0, reach * 0.99) >= 0.01, "{:?}", falloff);
+        assert!(falloff.weight(2.0, reach * 1.01) < 0.01, "{:?}", falloff);
+    }
+}
+
+#[test]
+fn test_heightmap_formats() {
+    let plain = Heightmap::from_pgm(b"P2\n# comment\n3 2\n4\n0 1 2\n3 4 4\n").unwrap();
+    let binary = Heightmap::from_pgm(b"P5 3 2 4\n\x00\x01\x02\x03\x04\x04").unwrap();
+    let csv = Heightmap::from_csv("0, 0.25, 0.5\n0.75, 1, 1\n").unwrap();
+    assert_eq!(plain, binary);
+    assert_eq!(plain, csv);
+
+    assert_eq!(csv.sample(0.0, 0.0), 0.0);
+    assert_eq!(csv.sample(1.0, 1.0), 1.0);
+    assert_eq!(csv.sample(0.25, 0.0), 0.125);
+    assert_eq!(csv.sample(0.5, 0.5), 0.625);
+    assert!(Heightmap::from_csv("1,2\n3\n").is_err());
+}
@@ -2,12 +2,14 @@ use std::time::Instant;
 
 use cgmath::prelude::*;
 use cgmath::Vector3;
-use cgmath::{Deg, Rad};
 use rand::Rng;
 use rayon::prelude::*;
 
 use super::actor::Agent;
 use crate::swarm::genome::SwarmGenome;
+use crate::swarm::observer::{NoopObserver, StepControl, StepObserver, StepPhase};
+use crate::swarm::stats::StepStats;
+use crate::swarm::urge::{ForceBreakdown, UrgeContext, URGES};
 use crate::{
     swarm::world::{ChunkedWorld, World},
     utils::*,
@@ -23,199 +25,310 @@ pub struct SwarmGrammar {
 }
 
 impl SwarmGrammar {
-    pub fn step(&mut self, rnd: &mut impl Rng) {
+    pub fn step(&mut self, rnd: &mut impl Rng) -> StepStats {
+        self.step_observed(rnd, &mut NoopObserver)
+            .expect("Step was aborted without an observer!")
+    }
+
+    /// Performs one step and reports every phase to `observer`.
+    ///
+    /// Returns `None` if the observer aborted the step.
+    pub fn step_observed(
+        &mut self,
+        rnd: &mut impl Rng,
+        observer: &mut impl StepObserver,
+    ) -> Option<StepStats> {
+        if observer.before_step(&self.world) == StepControl::Abort {
+            return None;
+        }
+
+        let mut stats = StepStats::default();
+        let escaped_agents = self.world.get_escaped_agent_count();
+        let escaped_artifacts = self.world.get_escaped_artifact_count();
+        self.run_phases(rnd, observer, &mut stats)?;
+        stats.escaped_agents = self.world.get_escaped_agent_count() - escaped_agents;
+        stats.escaped_artifacts = self.world.get_escaped_artifact_count() - escaped_artifacts;
+        self.world.finish_iteration();
+        stats.count_actors(&self.world, &self.genome);
+
+        observer.after_step(&stats, &self.world);
+        Some(stats)
+    }
+
+    fn run_phases(
+        &mut self,
+        rnd: &mut impl Rng,
+        observer: &mut impl StepObserver,
+        stats: &mut StepStats,
+    ) -> Option<()> {
         // Replace Agents
         // Recalc Agents
         // Spawn Artifacts
         // Recalc Buoys
 
-        let mut printfoo: Vec<String> = vec![format!(
-            "{:5} Agents {:5} Artifacts:",
-            self.world.get_all_agents().count(),
-            self.world.get_all_artifacts().count()
-        )];
-
         // 1. Replace by Rules          -------------------------------------
-        self.genome.tick();
-        let mut start = Instant::now();
-        self.world.replace_by(&self.genome, rnd);
-        printfoo.push(format!(
-            "replacement {:>7} ",
-            format!("{:.1?}", start.elapsed())
-        ));
+        stats.replacement = self.run_phase(StepPhase::Replacement, observer, stats, |sg| {
+            sg.genome.tick();
+            sg.world.replace_by(&sg.genome, rnd)
+        })?;
 
         // 2. Recalculate Velocities    -------------------------------------
-        start = Instant::now();
-        self.recalc_agent(rnd);
-        printfoo.push(format!("recalc {:>7} ", format!("{:.1?}", start.elapsed())));
+        let integration = self.genome.integration;
+        for substep in 0..integration.substeps {
+            let time = self.world.get_iteration() as f32 + substep as f32 * integration.dt();
+            let (moved_agents, breakdowns): (Vec<_>, Vec<_>) =
+                self.run_phase(StepPhase::Movement, observer, stats, |sg| {
+                    sg.calc_moved_agents(rnd, time).into_iter().unzip()
+                })?;
+            observer.on_forces(&breakdowns);
+            stats.out_of_bounds += moved_agents.iter().filter(|agent| agent.is_none()).count();
+            let updates = breakdowns
+                .iter()
+                .map(|breakdown| breakdown.agent)
+                .zip(moved_agents)
+                .collect();
+            self.run_phase(StepPhase::Cells, observer, stats, |sg| {
+                sg.world.update_agents(updates)
+            })?;
+        }
+        if !self.genome.fields.is_empty() {
+            self.run_phase(StepPhase::Fields, observer, stats, |sg| {
+                sg.world.update_fields(&sg.genome)
+            })?;
+        }
 
         // 3. Recalculate Buoys         -------------------------------------
-        start = Instant::now();
-        self.world.update_terrain((
-            &self.genome.terrain_influences.0,
-            &self.genome.terrain_influences.1,
-        ));
-        printfoo.push(format!(
-            "buoys rec {:>7} ",
-            format!("{:.1?}", start.elapsed())
-        ));
-        let pr = printfoo.join(" ");
-        println!("{}", pr);
+        self.run_phase(StepPhase::Terrain, observer, stats, |sg| {
+            sg.world.update_terrain(
+                (
+                    &sg.genome.terrain_influences.0,
+                    &sg.genome.terrain_influences.1,
+                ),
+                (
+                    &sg.genome.terrain_behaviors.0,
+                    &sg.genome.terrain_behaviors.1,
+                ),
+                sg.genome.terrain_update,
+            )
+        })?;
+
+        // 4. Erode Terrain             -------------------------------------
+        if !self.genome.terrain_erosion.is_enabled() {
+            return Some(());
+        }
+        self.run_phase(StepPhase::Erosion, observer, stats, |sg| {
+            sg.world.erode_terrain(&sg.genome.terrain_erosion)
+        })
+    }
+
+    /// Runs `phase` between the observer hooks, `None` if the observer aborted.
+    fn run_phase<T>(
+        &mut self,
+        phase: StepPhase,
+        observer: &mut impl StepObserver,
+        stats: &mut StepStats,
+        run: impl FnOnce(&mut Self) -> T,
+    ) -> Option<T> {
+        if observer.before_phase(phase, &self.world) == StepControl::Abort {
+            return None;
+        }
+        let start = Instant::now();
+        let result = run(self);
+        stats.add_time(phase, start.elapsed());
+        match observer.after_phase(phase, stats.get_time(phase), &self.world) {
+            StepControl::Continue => Some(result),
+            StepControl::Abort => None,
+        }
     }
 
     pub fn recalc_agent(&mut self, rnd: &mut impl Rng) {
+        let time = self.world.get_iteration() as f32;
+        let recalculated = self
+            .calc_moved_agents(rnd, time)
+            .into_iter()
+            .map(|(agent, breakdown)| (breakdown.agent, agent))
+            .collect();
+        self.world.update_agents(recalculated);
+    }
+
+    /// Moved version of every agent in the order of `World::get_all_agents` after a substep
+    /// starting at `time` and the forces that moved it. The agent is `None` if it was killed
+    /// for leaving the world bounds.
+    fn calc_moved_agents(
+        &self,
+        rnd: &mut impl Rng,
+        time: f32,
+    ) -> Vec<(Option<Agent>, ForceBreakdown)> {
         let agent_random_pairs: Vec<_> = self
             .world
             .get_all_agents()
             .map(|a| (random_one(rnd), a))
             .collect();
-        let mut recalculated: Vec<Agent> = agent_random_pairs
+        agent_random_pairs
             .par_iter()
-            .map(|a| self.move_agents(*a))
-            .collect();
-        recalculated.sort_by_key(|agent| agent.id);
-        self.world.set_agents(recalculated);
+            .map(|a| self.move_agents(*a, time))
+            .collect()
     }
 
-    fn move_agents(&self, (randomness, agent): (Vector3<f32>, &Agent)) -> Agent {
+    fn calc_force_breakdown(
+        &self,
+        randomness: Vector3<f32>,
+        agent: &Agent,
+        time: f32,
+    ) -> ForceBreakdown {
         let agent_species = &self.genome.get_species(agent);
 
         // 2.1. Prepare Vectors
 
-        let mut sep_vec = Vector3::zero();
-        let mut ali_vec = Vector3::zero();
-        let mut coh_vec = Vector3::zero();
-
-        let mut sep_counter = 0.0;
-        let mut view_counter = 0.0;
-        let mut artifact_view_counter = 0.0;
-
-        for (dist, other) in self
+        let mut neighbours = self
             .world
-            .get_context_within(agent_species.view_distance, agent.position)
-        {
-            if agent.id == other.get_id() {
-                continue;
-            }
-
-            // Find influence in influence vector
-            let inf_opt = agent_species
-                .influenced_by
-                .get(&other.get_surrounding_index());
-
-            // Default influence = 0
-            match inf_opt {
-                None => (),
-                Some(&influence) => {
-                    if dist < agent_species.view_distance {
-                        if dist < agent_species.sep_distance {
-                            let d = agent.position - other.get_position();
-                            let rev = d * (agent_species.sep_distance - d.magnitude());
-                            sep_vec += rev * influence;
-                            sep_counter += 1.0 * influence.abs();
-                        }
-
-                        let solid_angle =
-                            agent.velocity.angle(other.get_position() - agent.position);
-
-                        if solid_angle > Rad::from(Deg(agent_species.view_angle)) {
-                            continue;
-                        }
-
-                        use super::actor::Actor;
-
-                        match other {
-                            Actor::Agent(other_agent) => {
-                                ali_vec += other_agent.velocity * influence;
-                                coh_vec += other_agent.position * influence;
-                                view_counter += 1.0 * influence.abs();
-                            }
-                            Actor::Artifact(other_artifact) => {
-                                coh_vec += other_artifact.position * influence;
-                                artifact_view_counter += 1.0 * influence.abs();
-                            }
-                        }
-                    }
-                }
-            }
-        }
-
-        let sep_norm = safe_devide_mean(sep_vec, sep_counter);
-
-        let (ali_norm, coh_norm) = if view_counter > 0.0 {
-            let an = safe_devide_mean(ali_vec, view_counter);
-            let cn =
-                safe_devide_mean(coh_vec, view_counter + artifact_view_counter) - agent.position;
-            (an, cn)
-        } else {
-            (Vector3::<f32>::zero(), Vector3::<f32>::zero())
+            .get_context_within(agent_species.view_distance, agent.position);
+        neighbours.retain(|(_, other)| other.get_id() != agent.id);
+
+        let force: Vector3<f32> = self
+            .genome
+            .forces
+            .iter()
+            .map(|field| field.force_at(agent.position, time))
+            .sum();
+
+        let context = UrgeContext {
+            agent,
+            species: agent_species,
+            world: &self.world,
+            neighbours: &neighbours,
+            randomness,
         };
-        let cen_norm = agent.seed_center - agent.position;
-
-        let rnd_norm = randomness;
-
-        let base_dist = self.world.get_height(agent);
-        let floor = -Vector3::<f32>::unit_y() * (base_dist * base_dist);
-
-        let (gradient, normal) = self
-            .world
-            .get_gradient_and_normal(agent.position.x, agent.position.z);
-
-        let slope = self
-            .world
-            .get_slope(agent.position.x, agent.position.z, gradient);
 
         // 2.2. Actually Recalculate    ------------------
 
-        let mut acceleration = (agent_species.separation * sep_norm)
-            + (agent_species.alignment * ali_norm)
-            + (agent_species.cohesion * coh_norm)
-            + (agent_species.center * cen_norm)
-            + (agent_species.randomness * rnd_norm)
-            + (agent_species.floor * floor)
-            + (agent_species.gradient * gradient)
-            + (agent_species.slope * slope)
-            + (agent_species.normal * normal)
-            + agent_species.bias;
-
-        acceleration =
-            cgmath::ElementWise::mul_element_wise(acceleration, agent_species.axis_constraint);
+        let mut urge_sum = Vector3::zero();
+        let urges = URGES
+            .iter()
+            .zip(agent_species.urges.iter())
+            .map(|(urge, weight)| {
+                if *weight == 0.0 {
+                    return (urge.name(), Vector3::zero());
+                }
+                let contribution = *weight * urge.direction(&context);
+                urge_sum += contribution;
+                (urge.name(), contribution)
+            })
+            .collect();
+        let forces = agent_species.susceptibility * force;
+        let total = urge_sum + (forces + agent_species.bias);
 
+        let mut acceleration =
+            cgmath::ElementWise::mul_element_wise(total, agent_species.axis_constraint);
         acceleration = crate::utils::clip(acceleration, agent_species.max_acceleration);
 
-        let mut new_velocity = agent.velocity + acceleration;
-        new_velocity = crate::utils::clip(new_velocity, agent_species.max_speed);
-
-        if agent_species.pacekeeping > 0.0 {
-            new_velocity = agent_species.pacekeeping
-                * new_velocity.normalize_to(agent_species.normal_speed)
-                + (1.0 - agent_species.pacekeeping) * new_velocity;
+        ForceBreakdown {
+            agent: agent.id,
+            urges,
+            forces,
+            bias: agent_species.bias,
+            total,
+            acceleration,
+            unlimited_velocity: agent.velocity,
+            velocity: agent.velocity,
         }
+    }
 
-        let new_position = agent.position + new_velocity;
+    fn move_agents(
+        &self,
+        (randomness, agent): (Vector3<f32>, &Agent),
+        time: f32,
+    ) -> (Option<Agent>, ForceBreakdown) {
+        let agent_species = &self.genome.get_species(agent);
+        let mut breakdown = self.calc_force_breakdown(randomness, agent, time);
+        let acceleration = breakdown.acceleration;
+
+        let limit = |velocity: Vector3<f32>| {
+            let velocity = crate::utils::clip(velocity, agent_species.max_speed);
+            if agent_species.pacekeeping > 0.0 {
+                agent_species.pacekeeping * velocity.normalize_to(agent_species.normal_speed)
+                    + (1.0 - agent_species.pacekeeping) * velocity
+            } else {
+                velocity
+            }
+        };
+        let (mut new_velocity, offset) = self.genome.integration.advance(
+            agent.velocity,
+            agent.acceleration,
+            acceleration,
+            limit,
+        );
+        let (unlimited_velocity, _) = self.genome.integration.advance(
+            agent.velocity,
+            agent.acceleration,
+            acceleration,
+            |velocity| velocity,
+        );
+        breakdown.unlimited_velocity = unlimited_velocity;
+        breakdown.velocity = new_velocity;
+
+        let mut new_position = agent.position + offset;
+
+        if !agent_species.noclip {
+            // Stop at the first surface on the way, so fast agents can not pass through
+            let first_hit = self
+                .world
+                .get_obstacles()
+                .iter()
+                .filter_map(|obstacle| obstacle.first_hit(agent.position, new_position))
+                .fold(
+                    None,
+                    |first: Option<(f32, Vector3<f32>)>, hit| match first {
+                        Some(first) if first.0 <= hit.0 => Some(first),
+                        _ => Some(hit),
+                    },
+                );
+            if let Some((t, normal)) = first_hit {
+                new_position = agent.position + (new_position - agent.position) * t;
+                new_velocity -= normal * new_velocity.dot(normal).min(0.0);
+            }
+            for obstacle in self.world.get_obstacles() {
+                if let Some((dist, normal)) = obstacle.nearest_surface(new_position, 0.0) {
+                    // Push out onto the surface and drop the velocity into the obstacle
+                    new_position -= normal * dist;
+                    new_velocity -= normal * new_velocity.dot(normal).min(0.0);
+                }
+            }
+        }
 
         let new_floor = self.world.get_height_at(new_position.x, new_position.z);
-        let clipped_new_position = if !agent_species.noclip && new_floor > new_position.y {
-            Vector3::new(new_position.x, new_floor, new_position.z)
-        } else {
-            new_position
-        };
+        if !agent_species.noclip && new_floor > new_position.y {
+            new_position.y = new_floor;
+        }
+
+        // Applied last, so neither obstacles nor the floor push agents out of the bounds
+        if let Some(bounds) = &self.genome.bounds {
+            match bounds.confine(agent_species.boundary, new_position, new_velocity) {
+                Some((position, velocity)) => {
+                    new_position = position;
+                    new_velocity = velocity;
+                }
+                None => return (None, breakdown),
+            }
+        }
 
         let mut out_agent = agent.clone();
 
         out_agent.velocity = new_velocity;
-        out_agent.position = clipped_new_position;
+        out_agent.position = new_position;
+        out_agent.acceleration = Some(acceleration);
         out_agent.energy -= agent_species
             .energy
             .on_movement
-            .get(agent.velocity.magnitude());
+            .get(agent.velocity.magnitude())
+            * self.genome.integration.dt();
         assert!(
             out_agent.energy.is_finite(),
             "Calculated energy not finite! {:?}=>{}",
             out_agent.velocity,
             out_agent.energy
         );
-        out_agent
+        (Some(out_agent), breakdown)
     }
 
     pub fn get_world(&self) -> &ChunkedWorld {
@@ -225,10 +338,131 @@ impl SwarmGrammar {
     pub fn from(genome: SwarmGenome, mut rnd: &mut impl rand::Rng) -> SwarmGrammar {
         let mut uid_gen = crate::utils::UidGen::default();
         let (agents, artifacts) = genome.get_start(&mut rnd, &mut uid_gen);
-        let mut world =
-            ChunkedWorld::new(agents, genome.terrain_size, genome.terrain_spacing, uid_gen);
+        let mut world = ChunkedWorld::new(
+            agents,
+            genome.get_terrain_size(),
+            genome.terrain_spacing,
+            genome.terrain_origin,
+            genome.get_cell_size(),
+            genome.grid.overflow,
+            uid_gen,
+        );
         world.insert_artifacts(artifacts);
 
+        if let Some(terrain) = &genome.terrain_heightmap {
+            world.apply_heightmap(&terrain.heightmap, &terrain.config);
+        }
+
+        world.set_obstacles(genome.obstacles.clone());
+        world.init_fields(&genome.fields);
+
         SwarmGrammar { genome, world }
     }
 }
+
+#[test]
+fn test_force_breakdown_adds_up() {
+    use std::collections::HashMap;
+
+    struct Forces(Vec<ForceBreakdown>);
+    impl StepObserver for Forces {
+        fn on_forces(&mut self, breakdowns: &[ForceBreakdown]) {
+            self.0.extend_from_slice(breakdowns);
+        }
+    }
+
+    let (mut grammar, mut rnd) = crate::utils::sample_grammar("terrain_test_config.json", 3);
+    for _ in 0..5 {
+        grammar.step(&mut rnd);
+    }
+    let mut forces = Forces(vec![]);
+    grammar.step_observed(&mut rnd, &mut forces).unwrap();
+
+    let agents: HashMap<_, _> = grammar
+        .world
+        .get_all_agents()
+        .map(|agent| (agent.id, agent))
+        .collect();
+    assert_eq!(forces.0.len(), agents.len());
+    for breakdown in &forces.0 {
+        let agent = agents[&breakdown.agent];
+        let species = grammar.genome.get_species(agent);
+        assert_eq!(breakdown.urges.len(), crate::swarm::urge::URGES.len());
+        if species.urges.get("cohesion") == Some(0.0) {
+            assert_eq!(breakdown.urge("cohesion"), Some(Vector3::zero()));
+        }
+
+        let sum = breakdown
+            .urges
+            .iter()
+            .fold(breakdown.forces + breakdown.bias, |sum, (_, v)| sum + v);
+        assert!((sum - breakdown.total).magnitude() < 1e-4);
+        assert!(breakdown.acceleration.magnitude() <= species.max_acceleration + 1e-4);
+        assert!(breakdown.clipped() >= 0.0);
+
+        // The agent moved by exactly these forces
+        assert_eq!(agent.acceleration, Some(breakdown.acceleration));
+        assert_eq!(agent.velocity, breakdown.velocity);
+        if species.pacekeeping == 0.0 {
+            assert!(breakdown.velocity.magnitude() <= species.max_speed + 1e-4);
+        }
+        assert!(breakdown.velocity_clipped() >= 0.0);
+    }
+}
+
+#[test]
+fn test_substeps_only_refine_movement() {
+    use crate::swarm::genome::energy::MovementEnergy;
+    use crate::swarm::genome::force::{ForceField, ForceKind};
+    use crate::swarm::genome::integration::{Integration, Integrator};
+    use crate::swarm::urge::UrgeWeights;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    let step_once = |integrator, substeps| {
+        let mut genome = crate::utils::sample_genome("context_config.json");
+        for species in genome.species_map.iter_mut() {
+            species.urges = UrgeWeights::default();
+            species.bias = Vector3::zero();
+            species.max_speed = 10.0;
+            species.max_acceleration = 10.0;
+            species.axis_constraint = Vector3::new(1.0, 1.0, 1.0);
+            species.energy.on_movement = MovementEnergy::Constant(1.0);
+        }
+        genome.forces = vec![ForceField {
+            kind: ForceKind::Uniform([0.0, 0.0, 1.0]),
+            pulse: None,
+        }];
+        genome.integration = Integration {
+            substeps,
+            integrator,
+        };
+        let mut rnd = StdRng::seed_from_u64(5);
+        let mut grammar = SwarmGrammar::from(genome, &mut rnd);
+        grammar.step(&mut rnd);
+        let mut agents: Vec<_> = grammar.world.get_all_agents().cloned().collect();
+        agents.sort_by_key(|agent| agent.id);
+        agents
+    };
+
+    let euler = step_once(Integrator::SemiImplicitEuler, 1);
+    let euler_halved = step_once(Integrator::SemiImplicitEuler, 2);
+    let verlet = step_once(Integrator::VelocityVerlet, 1);
+    let verlet_halved = step_once(Integrator::VelocityVerlet, 2);
+    assert!(!euler.is_empty());
+    assert_eq!(euler.len(), euler_halved.len());
+    for (((a, b), c), d) in euler
+        .iter()
+        .zip(&euler_halved)
+        .zip(&verlet)
+        .zip(&verlet_halved)
+    {
+        assert_eq!(a.id, b.id);
+        // replacement and movement energy are per step, independent of the substeps
+        assert!((a.energy - b.energy).abs() < 1e-5);
+        assert!((a.velocity - b.velocity).magnitude() < 1e-5);
+        // from rest, euler moves 1 in one substep and 0.25 + 0.5 in two, verlet 0.5 exactly
+        assert!((a.position.z - b.position.z - 0.25).abs() < 1e-4);
+        assert!((c.position - d.position).magnitude() < 1e-4);
+        assert!((b.position.z - c.position.z - 0.25).abs() < 1e-4);
+    }
+}
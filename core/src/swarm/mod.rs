@@ -1,5 +1,9 @@
 pub mod actor;
+mod erosion;
 pub mod evo;
 pub mod genome;
 pub mod grammar;
+pub mod observer;
+pub mod stats;
+pub mod urge;
 pub mod world;
@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+use super::{
+    stats::StepStats,
+    urge::ForceBreakdown,
+    world::{ChunkedWorld, World},
+};
+
+/// The phases of a single `SwarmGrammar::step`, in execution order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StepPhase {
+    /// Agents are replaced according to their rules.
+    Replacement,
+    /// New velocities and positions of all agents are calculated. Runs once per substep of
+    /// the integration, alternating with `Cells`.
+    Movement,
+    /// The moved agents are written back into the world cells.
+    Cells,
+    /// The agents deposit into the scalar fields, which then diffuse and evaporate. Skipped
+    /// unless the genome has fields.
+    Fields,
+    /// The terrain is updated by the influencing actors.
+    Terrain,
+    /// The terrain is smoothed and eroded. Skipped unless the genome enables erosion.
+    Erosion,
+}
+
+/// Returned by the observer hooks to decide whether the step goes on.
+///
+/// An aborted step stops before the next phase, so the world is always left
+/// in a consistent state. Aborting after `Movement` discards the movement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepControl {
+    Continue,
+    Abort,
+}
+
+/// Hooks into `SwarmGrammar::step_observed`. Every hook defaults to a no-op.
+pub trait StepObserver {
+    fn before_step(&mut self, _world: &ChunkedWorld) -> StepControl {
+        StepControl::Continue
+    }
+
+    fn before_phase(&mut self, _phase: StepPhase, _world: &ChunkedWorld) -> StepControl {
+        StepControl::Continue
+    }
+
+    fn after_phase(
+        &mut self,
+        _phase: StepPhase,
+        _elapsed: Duration,
+        _world: &ChunkedWorld,
+    ) -> StepControl {
+        StepControl::Continue
+    }
+
+    /// Called after every `Movement` phase which was not aborted, with the forces acting on
+    /// every agent in it, in the order of `World::get_all_agents`.
+    fn on_forces(&mut self, _breakdowns: &[ForceBreakdown]) {}
+
+    /// Called once the step ran through all phases. Not called on abort.
+    fn after_step(&mut self, _stats: &StepStats, _world: &ChunkedWorld) {}
+}
+
+/// Observes nothing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopObserver;
+
+impl StepObserver for NoopObserver {}
+
+/// Prints actor counts and phase timings of every step to stdout.
+#[derive(Debug, Clone, Default)]
+pub struct PrintObserver {
+    line: Vec<String>,
+}
+
+impl StepObserver for PrintObserver {
+    fn before_step(&mut self, world: &ChunkedWorld) -> StepControl {
+        self.line = vec![format!(
+            "{:5} Agents {:5} Artifacts:",
+            world.get_agent_count(),
+            world.get_artifact_count()
+        )];
+        StepControl::Continue
+    }
+
+    fn after_phase(
+        &mut self,
+        phase: StepPhase,
+        elapsed: Duration,
+        _world: &ChunkedWorld,
+    ) -> StepControl {
+        let label = match phase {
+            StepPhase::Replacement => "replacement",
+            StepPhase::Movement => "recalc",
+            StepPhase::Cells => "cells",
+            StepPhase::Fields => "fields",
+            StepPhase::Terrain => "buoys rec",
+            StepPhase::Erosion => "erosion",
+        };
+        self.line
+            .push(format!("{} {:>7} ", label, format!("{:.1?}", elapsed)));
+        StepControl::Continue
+    }
+
+    fn after_step(&mut self, _stats: &StepStats, _world: &ChunkedWorld) {
+        println!("{}", self.line.join(" "));
+    }
+}
+
+#[test]
+fn test_abort_discards_movement() {
+    use crate::swarm::actor::Agent;
+
+    struct AbortAfter(StepPhase, Vec<StepPhase>);
+    impl StepObserver for AbortAfter {
+        fn after_phase(&mut self, phase: StepPhase, _: Duration, _: &ChunkedWorld) -> StepControl {
+            self.1.push(phase);
+            if phase == self.0 {
+                StepControl::Abort
+            } else {
+                StepControl::Continue
+            }
+        }
+        fn after_step(&mut self, _: &StepStats, _: &ChunkedWorld) {
+            panic!("Aborted step was finished!");
+        }
+    }
+
+    let (mut grammar, mut rnd) = crate::utils::sample_grammar("single_config.json", 3);
+    let before: Vec<Agent> = grammar.world.get_all_agents().cloned().collect();
+
+    let mut observer = AbortAfter(StepPhase::Movement, vec![]);
+    let stats = grammar.step_observed(&mut rnd, &mut observer);
+
+    let after: Vec<Agent> = grammar.world.get_all_agents().cloned().collect();
+    assert_eq!(stats, None);
+    assert_eq!(
+        observer.1,
+        vec![StepPhase::Replacement, StepPhase::Movement]
+    );
+    assert_eq!(before, after);
+}
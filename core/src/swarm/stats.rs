@@ -0,0 +1,159 @@
+use std::time::Duration;
+
+use serde::Serialize;
+
+use super::{
+    genome::SwarmGenome,
+    observer::StepPhase,
+    world::{ChunkedWorld, World},
+};
+
+/// What happened during one `World::replace_by`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ReplacementStats {
+    /// Agents with a new uid.
+    pub births: usize,
+    /// Agents which were not kept by their rule, including agents without an applicable rule.
+    pub deaths: usize,
+    /// Newly spawned artifacts.
+    pub spawned_artifacts: usize,
+    /// How often every rule was applied, indexed by species and rule.
+    pub rule_applications: Vec<Vec<usize>>,
+}
+
+impl ReplacementStats {
+    pub fn new(genome: &SwarmGenome) -> ReplacementStats {
+        ReplacementStats {
+            rule_applications: genome
+                .species_map
+                .iter()
+                .map(|species| vec![0; species.rules.len()])
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Timings and counts of a single `SwarmGrammar::step`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct StepStats {
+    pub replacement_time: Duration,
+    pub movement_time: Duration,
+    pub cells_time: Duration,
+    pub fields_time: Duration,
+    pub terrain_time: Duration,
+    pub erosion_time: Duration,
+    /// Agents after the step, indexed by species.
+    pub agents_per_species: Vec<usize>,
+    /// Artifacts after the step, indexed by artifact type.
+    pub artifacts_per_type: Vec<usize>,
+    pub replacement: ReplacementStats,
+    /// Agents removed for leaving the world bounds.
+    pub out_of_bounds: usize,
+    /// Agents removed for leaving the space the cells can represent, see
+    /// `CellOverflow::Remove`.
+    pub escaped_agents: usize,
+    pub escaped_artifacts: usize,
+}
+
+impl StepStats {
+    pub fn get_time(&self, phase: StepPhase) -> Duration {
+        match phase {
+            StepPhase::Replacement => self.replacement_time,
+            StepPhase::Movement => self.movement_time,
+            StepPhase::Cells => self.cells_time,
+            StepPhase::Fields => self.fields_time,
+            StepPhase::Terrain => self.terrain_time,
+            StepPhase::Erosion => self.erosion_time,
+        }
+    }
+
+    pub(crate) fn add_time(&mut self, phase: StepPhase, time: Duration) {
+        match phase {
+            StepPhase::Replacement => self.replacement_time += time,
+            StepPhase::Movement => self.movement_time += time,
+            StepPhase::Cells => self.cells_time += time,
+            StepPhase::Fields => self.fields_time += time,
+            StepPhase::Terrain => self.terrain_time += time,
+            StepPhase::Erosion => self.erosion_time += time,
+        }
+    }
+
+    pub fn total_time(&self) -> Duration {
+        self.replacement_time
+            + self.movement_time
+            + self.cells_time
+            + self.fields_time
+            + self.terrain_time
+            + self.erosion_time
+    }
+
+    pub fn get_agent_count(&self) -> usize {
+        self.agents_per_species.iter().sum()
+    }
+
+    pub fn get_artifact_count(&self) -> usize {
+        self.artifacts_per_type.iter().sum()
+    }
+
+    pub(crate) fn count_actors(&mut self, world: &ChunkedWorld, genome: &SwarmGenome) {
+        self.agents_per_species = vec![0; genome.species_map.len()];
+        world
+            .get_all_agents()
+            .for_each(|agent| self.agents_per_species[agent.species_index.0] += 1);
+
+        self.artifacts_per_type = vec![0; genome.artifact_map.len()];
+        world
+            .get_all_artifacts()
+            .for_each(|artifact| self.artifacts_per_type[artifact.artifact_index.0] += 1);
+    }
+}
+
+#[test]
+fn test_step_stats_match_world() {
+    use crate::swarm::grammar::SwarmGrammar;
+
+    let (mut grammar, mut rnd) = crate::utils::sample_grammar("context_config.json", 1);
+
+    let mut births = 0;
+    for _ in 0..6 {
+        let before = grammar.world.get_agent_count();
+        let stats = grammar.step(&mut rnd);
+        let repl = &stats.replacement;
+
+        assert_eq!(stats.get_agent_count(), grammar.world.get_agent_count());
+        assert_eq!(
+            stats.get_artifact_count(),
+            grammar.world.get_artifact_count()
+        );
+        assert_eq!(
+            before + repl.births - repl.deaths - stats.out_of_bounds - stats.escaped_agents,
+            stats.get_agent_count()
+        );
+        births += repl.births;
+    }
+    assert!(births > 0);
+
+    // Cells so small that agents wandering beyond the ones above escape
+    let outermost = grammar
+        .world
+        .get_all_agents()
+        .map(|agent| agent.position.x.abs().max(agent.position.z.abs()))
+        .fold(0.0, f32::max);
+    let mut genome = crate::utils::sample_genome("context_config.json");
+    genome.grid.cell_size = Some(outermost * 1.01 / i32::MAX as f32);
+    genome.grid.overflow = crate::swarm::genome::CellOverflow::Remove;
+    let mut grammar = SwarmGrammar::from(genome, &mut rnd);
+    let mut escaped = 0;
+    for _ in 0..6 {
+        let before = grammar.world.get_agent_count();
+        let stats = grammar.step(&mut rnd);
+        let repl = &stats.replacement;
+        assert_eq!(
+            before + repl.births - repl.deaths - stats.out_of_bounds - stats.escaped_agents,
+            stats.get_agent_count()
+        );
+        escaped += stats.escaped_agents;
+    }
+    assert!(escaped > 0);
+}
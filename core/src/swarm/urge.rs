@@ -0,0 +1,446 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::marker::PhantomData;
+
+use cgmath::{Deg, InnerSpace, Rad, Vector3, Zero};
+use serde::de::{IgnoredAny, MapAccess, Visitor};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::actor::{ActorRef, Agent};
+use super::genome::Species;
+use super::world::{ChunkedWorld, World};
+use crate::utils::{safe_devide_mean, Uid};
+
+/// A steering behavior, contributing its direction times the weight the species gives it to
+/// the acceleration of an agent.
+///
+/// New urges only have to be added to `URGES`, their weight is then read from the species
+/// config under their name.
+pub trait Urge: Sync {
+    /// Key of the weight in the species config.
+    fn name(&self) -> &'static str;
+    fn direction(&self, context: &UrgeContext) -> Vector3<f32>;
+    /// Weight of the urge for species which do not name it.
+    fn default_weight(&self) -> f32 {
+        0.0
+    }
+}
+
+/// What an urge may look at to steer an agent.
+pub struct UrgeContext<'a> {
+    pub agent: &'a Agent,
+    pub species: &'a Species,
+    pub world: &'a ChunkedWorld,
+    /// Actors within the view distance of the species and their distance, without the agent.
+    pub neighbours: &'a [(f32, ActorRef<'a>)],
+    /// Random vector drawn for this agent and step.
+    pub randomness: Vector3<f32>,
+}
+
+impl<'a> UrgeContext<'a> {
+    /// Neighbours the species is influenced by, with their distance and the influence.
+    pub fn influencing(&self) -> impl Iterator<Item = (f32, ActorRef<'a>, f32)> + '_ {
+        self.neighbours.iter().filter_map(move |(dist, other)| {
+            self.species
+                .influenced_by
+                .get(&other.get_surrounding_index())
+                .map(|influence| (*dist, *other, *influence))
+        })
+    }
+
+    /// Influencing neighbours within the view angle of the agent and their influence.
+    pub fn visible(&self) -> impl Iterator<Item = (ActorRef<'a>, f32)> + '_ {
+        let view_angle = Rad::from(Deg(self.species.view_angle));
+        self.influencing()
+            .filter(move |(_, other, _)| {
+                let solid_angle = self
+                    .agent
+                    .velocity
+                    .angle(other.get_position() - self.agent.position);
+                // The angle is NaN for agents without velocity, which see all around
+                solid_angle.partial_cmp(&view_angle) != Some(Ordering::Greater)
+            })
+            .map(|(_, other, influence)| (other, influence))
+    }
+
+    /// Gradient and normal of the terrain below the agent.
+    pub fn terrain_gradient_and_normal(&self) -> (Vector3<f32>, Vector3<f32>) {
+        let position = self.agent.position;
+        self.world.get_gradient_and_normal(position.x, position.z)
+    }
+}
+
+/// Weighted contributions to the acceleration of an agent in one step, and how its velocity
+/// was limited.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForceBreakdown {
+    pub agent: Uid,
+    /// Weighted direction of every urge, named and ordered like `URGES`.
+    pub urges: Vec<(&'static str, Vector3<f32>)>,
+    /// Global force fields, scaled by the susceptibility of the species.
+    pub forces: Vector3<f32>,
+    pub bias: Vector3<f32>,
+    /// Sum of all contributions.
+    pub total: Vector3<f32>,
+    /// `total` after the axis constraint and clipping to the maximum acceleration, as added
+    /// to the velocity.
+    pub acceleration: Vector3<f32>,
+    /// Velocity the acceleration leads to without any limit.
+    pub unlimited_velocity: Vector3<f32>,
+    /// `unlimited_velocity` after clipping to the maximum speed and pacekeeping. Obstacles
+    /// and world bounds may still change it.
+    pub velocity: Vector3<f32>,
+}
+
+impl ForceBreakdown {
+    /// Weighted direction of the urge called `name`.
+    pub fn urge(&self, name: &str) -> Option<Vector3<f32>> {
+        self.urges
+            .iter()
+            .find(|(urge, _)| *urge == name)
+            .map(|(_, contribution)| *contribution)
+    }
+
+    /// Length of the acceleration removed by the axis constraint and clipping.
+    pub fn clipped(&self) -> f32 {
+        (self.total - self.acceleration).magnitude()
+    }
+
+    /// Length of the velocity change by the maximum speed and pacekeeping.
+    pub fn velocity_clipped(&self) -> f32 {
+        (self.unlimited_velocity - self.velocity).magnitude()
+    }
+}
+
+/// All known urges, in the order they are summed up.
+pub static URGES: &[&dyn Urge] = &[
+    &Separation,
+    &Alignment,
+    &Cohesion,
+    &Center,
+    &Randomness,
+    &Floor,
+    &Gradient,
+    &Slope,
+    &Avoidance,
+    &Normal,
+    &Trail,
+];
+
+/// Urges species configs have always listed. Their weights are written even at the default,
+/// those of the other urges only if they differ from it, so saved genomes keep their format.
+const LISTED_URGES: &[&str] = &[
+    "separation",
+    "alignment",
+    "cohesion",
+    "randomness",
+    "center",
+    "floor",
+    "gradient",
+    "normal",
+    "slope",
+];
+
+/// Position of the urge called `name` in `URGES`.
+pub fn urge_index(name: &str) -> Option<usize> {
+    URGES.iter().position(|urge| urge.name() == name)
+}
+
+/// Mean offset away from the neighbours closer than the separation distance.
+struct Separation;
+impl Urge for Separation {
+    fn name(&self) -> &'static str {
+        "separation"
+    }
+    fn direction(&self, context: &UrgeContext) -> Vector3<f32> {
+        let sep_distance = context.species.sep_distance;
+        let mut sum = Vector3::zero();
+        let mut counter = 0.0;
+        for (dist, other, influence) in context.influencing() {
+            if dist < sep_distance {
+                let d = context.agent.position - other.get_position();
+                sum += d * (sep_distance - d.magnitude()) * influence;
+                counter += 1.0 * influence.abs();
+            }
+        }
+        safe_devide_mean(sum, counter)
+    }
+}
+
+/// Mean velocity of the visible agents.
+struct Alignment;
+impl Urge for Alignment {
+    fn name(&self) -> &'static str {
+        "alignment"
+    }
+    fn direction(&self, context: &UrgeContext) -> Vector3<f32> {
+        let mut sum = Vector3::zero();
+        let mut counter = 0.0;
+        for (other, influence) in context.visible() {
+            if let ActorRef::Agent(other_agent) = other {
+                sum += other_agent.velocity * influence;
+                counter += 1.0 * influence.abs();
+            }
+        }
+        if counter > 0.0 {
+            safe_devide_mean(sum, counter)
+        } else {
+            Vector3::zero()
+        }
+    }
+}
+
+/// Offset to the mean position of the visible actors, if any agent is visible.
+struct Cohesion;
+impl Urge for Cohesion {
+    fn name(&self) -> &'static str {
+        "cohesion"
+    }
+    fn direction(&self, context: &UrgeContext) -> Vector3<f32> {
+        let mut sum = Vector3::zero();
+        let (mut agent_counter, mut artifact_counter) = (0.0, 0.0);
+        for (other, influence) in context.visible() {
+            sum += other.get_position() * influence;
+            match other {
+                ActorRef::Agent(_) => agent_counter += 1.0 * influence.abs(),
+                ActorRef::Artifact(_) => artifact_counter += 1.0 * influence.abs(),
+            }
+        }
+        if agent_counter > 0.0 {
+            safe_devide_mean(sum, agent_counter + artifact_counter) - context.agent.position
+        } else {
+            Vector3::zero()
+        }
+    }
+}
+
+/// Toward the position the agent was seeded at.
+struct Center;
+impl Urge for Center {
+    fn name(&self) -> &'static str {
+        "center"
+    }
+    fn direction(&self, context: &UrgeContext) -> Vector3<f32> {
+        context.agent.seed_center - context.agent.position
+    }
+}
+
+struct Randomness;
+impl Urge for Randomness {
+    fn name(&self) -> &'static str {
+        "randomness"
+    }
+    fn direction(&self, context: &UrgeContext) -> Vector3<f32> {
+        context.randomness
+    }
+}
+
+/// Down toward the terrain, growing quadratically with the height above it.
+struct Floor;
+impl Urge for Floor {
+    fn name(&self) -> &'static str {
+        "floor"
+    }
+    fn direction(&self, context: &UrgeContext) -> Vector3<f32> {
+        let base_dist = context.world.get_height(context.agent);
+        -Vector3::<f32>::unit_y() * (base_dist * base_dist)
+    }
+}
+
+struct Gradient;
+impl Urge for Gradient {
+    fn name(&self) -> &'static str {
+        "gradient"
+    }
+    fn direction(&self, context: &UrgeContext) -> Vector3<f32> {
+        context.terrain_gradient_and_normal().0
+    }
+}
+
+struct Slope;
+impl Urge for Slope {
+    fn name(&self) -> &'static str {
+        "slope"
+    }
+    fn direction(&self, context: &UrgeContext) -> Vector3<f32> {
+        let position = context.agent.position;
+        let (gradient, _) = context.terrain_gradient_and_normal();
+        context.world.get_slope(position.x, position.z, gradient)
+    }
+}
+
+/// Away from the obstacles within view distance.
+struct Avoidance;
+impl Urge for Avoidance {
+    fn name(&self) -> &'static str {
+        "avoidance"
+    }
+    fn direction(&self, context: &UrgeContext) -> Vector3<f32> {
+        let view_distance = context.species.view_distance;
+        if view_distance <= 0.0 {
+            return Vector3::new(0.0, 0.0, 0.0);
+        }
+        context
+            .world
+            .get_obstacles()
+            .iter()
+            .filter_map(|obstacle| obstacle.nearest_surface(context.agent.position, view_distance))
+            .map(|(dist, normal)| normal * (1.0 - dist / view_distance))
+            .sum()
+    }
+}
+
+struct Normal;
+impl Urge for Normal {
+    fn name(&self) -> &'static str {
+        "normal"
+    }
+    fn direction(&self, context: &UrgeContext) -> Vector3<f32> {
+        context.terrain_gradient_and_normal().1
+    }
+}
+
+/// Along the gradients of the fields the species follows, each weighted by its `follow`.
+struct Trail;
+impl Urge for Trail {
+    fn name(&self) -> &'static str {
+        "trail"
+    }
+    fn direction(&self, context: &UrgeContext) -> Vector3<f32> {
+        let fields = context.world.get_fields();
+        context
+            .species
+            .fields
+            .iter()
+            .filter(|interaction| interaction.follow != 0.0)
+            .map(|interaction| {
+                interaction.follow * fields[interaction.field].gradient(context.agent.position)
+            })
+            .sum()
+    }
+    /// Following is already weighted per field.
+    fn default_weight(&self) -> f32 {
+        1.0
+    }
+}
+
+/// Weight of every urge, indexed like `URGES` and serialized as a map from urge names to
+/// weights. Urges missing from the map have their default weight.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(transparent)]
+pub struct UrgeWeights(
+    #[serde(
+        serialize_with = "serialize_weights",
+        deserialize_with = "deserialize_weights"
+    )]
+    Vec<f32>,
+);
+
+impl UrgeWeights {
+    pub fn get(&self, name: &str) -> Option<f32> {
+        urge_index(name).map(|index| self.0[index])
+    }
+
+    /// Weights in the order of `URGES`.
+    pub fn iter(&self) -> impl Iterator<Item = &f32> {
+        self.0.iter()
+    }
+}
+
+impl Default for UrgeWeights {
+    fn default() -> UrgeWeights {
+        UrgeWeights(URGES.iter().map(|urge| urge.default_weight()).collect())
+    }
+}
+
+impl std::iter::FromIterator<f32> for UrgeWeights {
+    /// Collects weights given in the order of `URGES`.
+    fn from_iter<I: IntoIterator<Item = f32>>(iter: I) -> Self {
+        let weights: Vec<f32> = iter.into_iter().collect();
+        assert_eq!(weights.len(), URGES.len(), "Expected one weight per urge!");
+        UrgeWeights(weights)
+    }
+}
+
+/// Serializes values indexed like `URGES` as a map from urge names to values. Urges which are
+/// not in `LISTED_URGES` are skipped while they have the value `missing` reads them as.
+pub(crate) fn serialize_named<S: Serializer, T: Serialize + PartialEq>(
+    values: &[T],
+    serializer: S,
+    missing: impl Fn(&dyn Urge) -> T,
+) -> Result<S::Ok, S::Error> {
+    let written: Vec<_> = URGES
+        .iter()
+        .zip(values)
+        .filter(|(urge, value)| LISTED_URGES.contains(&urge.name()) || **value != missing(**urge))
+        .collect();
+    let mut map = serializer.serialize_map(Some(written.len()))?;
+    for (urge, value) in written {
+        map.serialize_entry(urge.name(), value)?;
+    }
+    map.end()
+}
+
+/// Reads a map from urge names to values into a vector indexed like `URGES`, using `missing`
+/// for the urges not in the map. Keys which do not name an urge are ignored, as the weights
+/// share their map with the other fields of a species.
+pub(crate) fn deserialize_named<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+    deserializer: D,
+    missing: impl Fn(&dyn Urge) -> T,
+) -> Result<Vec<T>, D::Error> {
+    struct NamedVisitor<T>(Vec<T>, PhantomData<T>);
+
+    impl<'de, T: Deserialize<'de>> Visitor<'de> for NamedVisitor<T> {
+        type Value = Vec<T>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a map from urge names to weights")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(mut self, mut map: A) -> Result<Vec<T>, A::Error> {
+            while let Some(name) = map.next_key::<String>()? {
+                match urge_index(&name) {
+                    Some(index) => self.0[index] = map.next_value()?,
+                    None => {
+                        map.next_value::<IgnoredAny>()?;
+                    }
+                }
+            }
+            Ok(self.0)
+        }
+    }
+
+    let values = URGES.iter().map(|urge| missing(*urge)).collect();
+    deserializer.deserialize_map(NamedVisitor(values, PhantomData))
+}
+
+fn serialize_weights<S: Serializer>(weights: &[f32], serializer: S) -> Result<S::Ok, S::Error> {
+    serialize_named(weights, serializer, |urge| urge.default_weight())
+}
+
+fn deserialize_weights<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<f32>, D::Error> {
+    deserialize_named(deserializer, |urge| urge.default_weight())
+}
+
+#[test]
+fn test_urge_weights_by_name() {
+    let weights: UrgeWeights = serde_json::from_str(r#"{ "cohesion": 0.5, "normal": 2 }"#).unwrap();
+    assert_eq!(weights.get("cohesion"), Some(0.5));
+    assert_eq!(weights.get("normal"), Some(2.0));
+    assert_eq!(weights.get("separation"), Some(0.0));
+    assert_eq!(weights.get("trail"), Some(1.0));
+    assert_eq!(weights.get("unknown"), None);
+
+    // Urges added later are only written once they differ from their default
+    let json = serde_json::to_value(&weights).unwrap();
+    assert_eq!(json["cohesion"], 0.5);
+    assert_eq!(json["separation"], 0.0);
+    assert_eq!(json.as_object().unwrap().len(), LISTED_URGES.len());
+    assert!(json.get("trail").is_none());
+
+    // Other fields of the species are no urges
+    let weights: UrgeWeights =
+        serde_json::from_str(r#"{ "slope": 0.5, "pacekeeping": 1, "rules": [] }"#).unwrap();
+    assert_eq!(weights.get("slope"), Some(0.5));
+}
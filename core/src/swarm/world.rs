@@ -9,9 +9,19 @@ use std::{cmp::Ordering, ops::Deref};
 use crate::{
     swarm::{
         actor::*,
-        genome::{SurroundingIndex, SwarmGenome},
+        erosion::HeightGrid,
+        genome::{
+            field::{FieldConfig, ScalarField},
+            obstacle::Obstacle,
+            replacement::ContextRule,
+            terrain::{
+                Heightmap, HeightmapConfig, InfluenceBehavior, TerrainErosion, TerrainUpdate,
+            },
+            CellOverflow, SurroundingIndex, SwarmGenome,
+        },
+        stats::ReplacementStats,
     },
-    utils::UidGen,
+    utils::{actor_rng, Uid, UidGen},
 };
 
 type AgentIterBox<'a> = Box<dyn Iterator<Item = &'a Agent> + 'a>;
@@ -19,11 +29,11 @@ type ArtifactIterBox<'a> = Box<dyn Iterator<Item = &'a Artifact> + 'a>;
 type BuoyIterBox<'a> = Box<dyn Iterator<Item = &'a Buoy> + 'a>;
 
 pub trait World {
-    fn replace_by(&mut self, genome: &SwarmGenome, rnd: &mut impl Rng);
+    fn replace_by(&mut self, genome: &SwarmGenome, rnd: &mut impl Rng) -> ReplacementStats;
 
     fn get_uid_gen(&mut self) -> &mut UidGen;
 
-    fn get_context_within(&self, range: f32, center_pos: Vector3<f32>) -> Vec<(f32, Actor)>;
+    fn get_context_within(&self, range: f32, center_pos: Vector3<f32>) -> Vec<(f32, ActorRef<'_>)>;
 
     fn get_all_agents(&self) -> AgentIterBox;
     fn get_all_artifacts(&self) -> ArtifactIterBox;
@@ -35,110 +45,155 @@ pub trait World {
     fn set_agents(&mut self, new_agents: Vec<Agent>);
     fn set_artifacts(&mut self, new_artifacts: Vec<Artifact>);
 
+    /// Replaces every agent by its updated version, or removes it if there is none. The
+    /// updates are keyed by the id of the agent they replace and have to be given in the order
+    /// of `get_all_agents`.
+    fn update_agents(&mut self, updated_agents: Vec<(Uid, Option<Agent>)>);
+
     fn get_agent_count(&self) -> usize;
     fn get_artifact_count(&self) -> usize;
     fn get_buoy_count(&self) -> usize;
 
-    fn update_terrain(&mut self, influences: (&[f32], &[f32]));
+    fn update_terrain(
+        &mut self,
+        influences: (&[f32], &[f32]),
+        behaviors: (&[InfluenceBehavior], &[InfluenceBehavior]),
+        update: TerrainUpdate,
+    );
+    /// Smooths and erodes the terrain after an update.
+    fn erode_terrain(&mut self, erosion: &TerrainErosion);
     fn get_height(&self, agent: &Agent) -> f32;
     fn get_height_at(&self, x: f32, z: f32) -> f32;
     fn get_gradient_and_normal(&self, xpos: f32, zpos: f32) -> (Vector3<f32>, Vector3<f32>);
     fn get_slope(&self, xpos: f32, zpos: f32, gradient: Vector3<f32>) -> Vector3<f32>;
 
-    fn get_size(&self) -> (usize, usize, f32);
+    /// Buoys along x and z, their spacing and the position of the terrain center.
+    fn get_size(&self) -> (usize, usize, f32, (f32, f32));
+
+    fn get_obstacles(&self) -> &[Obstacle];
+
+    fn get_fields(&self) -> &[ScalarField];
+    /// Lets every agent deposit into the fields, which then diffuse and evaporate.
+    fn update_fields(&mut self, genome: &SwarmGenome);
 }
 
 impl World for ChunkedWorld {
-    fn replace_by(&mut self, genome: &SwarmGenome, rnd: &mut impl Rng) {
-        if !genome.strategy.should_replace() {
-            return;
+    fn replace_by(&mut self, genome: &SwarmGenome, rnd: &mut impl Rng) -> ReplacementStats {
+        let mut stats = ReplacementStats::new(genome);
+        if !genome.should_replace() {
+            return stats;
         }
 
-        let mut new_agents: Vec<Agent> = Vec::with_capacity(self.get_agent_count());
-        let mut new_artifacts: Vec<Artifact> = Vec::with_capacity(self.get_artifact_count());
-
-        let mut uid_gen = self.uid_gen.clone();
-
-        self.get_all_agents().for_each(|agent: &Agent| {
-            let rules = genome.get_rules(&agent.species_index);
-
-            let max_range = rules
-                .iter()
-                .max_by(|a, b| a.range.partial_cmp(&b.range).unwrap_or(Ordering::Equal))
-                .map(|rule| rule.range);
-
-            let context: Vec<(f32, SurroundingIndex)> = if let Some(range) = max_range {
-                self.get_context_within(range, agent.position)
-                    .into_iter()
-                    .map(|(d, act)| (d, act.into()))
-                    .collect()
-            } else {
-                vec![]
-            };
-
-            let applicable_rules: Vec<_> = rules
-                .iter()
-                .filter(|rule| rule.is_applicable(&context))
-                .collect();
+        let step_seed: u64 = rnd.gen();
+        let agents: Vec<&Agent> = self.get_all_agents().collect();
 
-            let weight_sum: f32 = applicable_rules.iter().map(|rule| rule.weight).sum();
-            let threshold = rnd.gen_range(0.0, weight_sum + f32::EPSILON);
-
-            let mut gauge = 0.0;
-            for rule in applicable_rules {
-                gauge += rule.weight;
-                if gauge < threshold {
-                    continue;
+        // Every agent draws from its own random stream, so selection and replacement can run
+        // in any order. Agents whose strategy does not fire are kept as they are.
+        let mut selected_rules: Vec<_> = agents
+            .par_iter()
+            .map(|agent| {
+                let strategy = genome.get_strategy(&agent.species_index);
+                let mut agent_rnd = actor_rng(step_seed, agent.id);
+                let fires = strategy.should_replace()
+                    && (strategy.probability >= 1.0
+                        || agent_rnd.gen::<f32>() < strategy.probability);
+                if !fires {
+                    return (false, None, agent_rnd);
                 }
+                let rule = self.select_rule(agent, genome, &mut agent_rnd);
+                (true, rule, agent_rnd)
+            })
+            .collect();
 
-                let (mut new_ag, mut new_art) = rule.replace_agent(agent, genome, &mut uid_gen);
+        // Uids are handed out in agent order to keep them independent of the scheduling
+        let mut uid_gen = self.uid_gen;
+        let reserved_uids: Vec<(UidGen, UidGen)> = agents
+            .iter()
+            .zip(selected_rules.iter())
+            .map(|(agent, (_, rule, _))| {
+                let count = rule.map_or(0, |(_, rule)| rule.required_uids(agent, genome));
+                (uid_gen.reserve(count), uid_gen)
+            })
+            .collect();
 
-                new_agents.append(&mut new_ag);
-                new_artifacts.append(&mut new_art);
+        let replacements: Vec<(Vec<Agent>, Vec<Artifact>)> = agents
+            .par_iter()
+            .zip(selected_rules.par_iter_mut())
+            .zip(reserved_uids.into_par_iter())
+            .map(
+                |((agent, (fires, rule, agent_rnd)), (mut agent_uid_gen, reserved_end))| {
+                    let replacement = match (fires, rule) {
+                        (false, _) => (vec![(*agent).clone()], vec![]),
+                        (true, Some((_, rule))) => {
+                            rule.replace_agent(agent, genome, &mut agent_uid_gen, agent_rnd)
+                        }
+                        (true, None) => (vec![], vec![]),
+                    };
+                    debug_assert_eq!(
+                        agent_uid_gen, reserved_end,
+                        "Reserved uids were not used up!"
+                    );
+                    replacement
+                },
+            )
+            .collect();
 
-                break;
+        let mut new_agents: Vec<Agent> = Vec::with_capacity(self.get_agent_count());
+        let mut new_artifacts: Vec<Artifact> = Vec::with_capacity(self.get_artifact_count());
+        for ((parent, (_, rule, _)), (mut agents, mut artifacts)) in
+            agents.iter().zip(selected_rules.iter()).zip(replacements)
+        {
+            if let Some((rule_index, _)) = rule {
+                stats.rule_applications[parent.species_index.0][*rule_index] += 1;
             }
-        });
+            let born = agents.iter().filter(|agent| agent.id != parent.id).count();
+            stats.births += born;
+            if born == agents.len() {
+                stats.deaths += 1;
+            }
+            stats.spawned_artifacts += artifacts.len();
+
+            new_agents.append(&mut agents);
+            new_artifacts.append(&mut artifacts);
+        }
 
         self.uid_gen = uid_gen;
 
         self.delete_agents();
         self.insert_agents(new_agents);
         self.insert_artifacts(new_artifacts);
+        self.prune_cells();
+
+        stats
     }
 
     fn get_uid_gen(&mut self) -> &mut UidGen {
         &mut self.uid_gen
     }
 
-    fn get_context_within(&self, range: f32, center_pos: Vector3<f32>) -> Vec<(f32, Actor)> {
-        let _xz = Vector2::new(center_pos.x, center_pos.z);
-
-        let mut agents: Vec<(f32, Actor)> = self
-            .get_all_agents()
+    fn get_context_within(&self, range: f32, center_pos: Vector3<f32>) -> Vec<(f32, ActorRef<'_>)> {
+        let agents = self
+            .get_agents_at_least_within(range, center_pos)
             .map(|agent| {
                 (
                     MetricSpace::distance(center_pos, agent.position),
-                    agent.clone().into(),
+                    ActorRef::Agent(agent),
                 )
-            })
-            .filter(|(dist, _actor)| dist < &range)
-            .collect();
+            });
 
-        let mut artifacts: Vec<(f32, Actor)> = self
-            .get_all_artifacts()
+        let artifacts = self
+            .get_artifacts_at_least_within(range, center_pos)
             .map(|artifact| {
                 (
                     MetricSpace::distance(center_pos, artifact.position),
-                    artifact.clone().into(),
+                    ActorRef::Artifact(artifact),
                 )
-            })
-            .filter(|(dist, _art)| dist < &range)
-            .collect();
-
-        agents.append(&mut artifacts);
+            });
 
         agents
+            .chain(artifacts)
+            .filter(|(dist, _actor)| dist < &range)
+            .collect()
     }
 
     fn get_all_agents(&self) -> AgentIterBox {
@@ -167,10 +222,58 @@ impl World for ChunkedWorld {
     fn set_agents(&mut self, new_agents: Vec<Agent>) {
         self.delete_agents();
         self.insert_agents(new_agents);
+        self.prune_cells();
     }
     fn set_artifacts(&mut self, new_artifacts: Vec<Artifact>) {
         self.delete_artifacts();
         self.insert_artifacts(new_artifacts);
+        self.prune_cells();
+    }
+
+    fn update_agents(&mut self, updated_agents: Vec<(Uid, Option<Agent>)>) {
+        assert_eq!(
+            updated_agents.len(),
+            self.agent_count,
+            "Every agent has to be updated!"
+        );
+
+        let (cell_size, overflow) = (self.cell_size, self.overflow);
+        let mut crossing = std::mem::take(&mut self.crossing_agents);
+        let mut updated_agents = updated_agents.into_iter();
+
+        // Overwrite agents in place and only collect those which left their cell
+        let mut removed = 0;
+        for (coord, cell) in self.agent_cells.iter_mut() {
+            let mut kept = 0;
+            for slot in 0..cell.len() {
+                let (id, update) = updated_agents.next().unwrap();
+                assert_eq!(
+                    id, cell[slot].id,
+                    "Agents have to be updated in the order of get_all_agents!"
+                );
+                let agent = match update {
+                    Some(agent) => agent,
+                    None => {
+                        removed += 1;
+                        continue;
+                    }
+                };
+                if Coord::target(agent.position, cell_size, overflow) == Some(*coord) {
+                    cell[kept] = agent;
+                    kept += 1;
+                } else {
+                    crossing.push(agent);
+                }
+            }
+            cell.truncate(kept);
+        }
+
+        self.agent_count -= removed + crossing.len();
+        for agent in crossing.drain(..) {
+            self.insert_agent(agent);
+        }
+        self.crossing_agents = crossing;
+        self.prune_cells();
     }
 
     fn get_agent_count(&self) -> usize {
@@ -183,37 +286,35 @@ impl World for ChunkedWorld {
         self.terrain.sample_points.len() * self.terrain.sample_points[0].len()
     }
 
-    fn update_terrain(&mut self, influences: (&[f32], &[f32])) {
-        use super::actor::*;
-
+    fn update_terrain(
+        &mut self,
+        influences: (&[f32], &[f32]),
+        behaviors: (&[InfluenceBehavior], &[InfluenceBehavior]),
+        update: TerrainUpdate,
+    ) {
         fn update_buoy<'a>(
-            actors: &[Actor],
             b: &mut Buoy,
-            _spacing: f32,
-            influences: (&[f32], &[f32]),
+            anchor: f32,
+            influencers: impl Iterator<Item = &'a Influencer>,
         ) {
-            let mut influecers = 0.0;
-            let mut avg_ydist = 0.0;
+            let mut influecers = anchor;
+            let mut avg_ydist = anchor * (b.base - b.position.y);
 
             let bpos = Vector2::new(b.position.x, b.position.z);
 
-            for other in actors {
-                let (otherpos, influence_factor) = match other {
-                    Actor::Agent(ag) => (ag.position, influences.0[ag.species_index.0]),
-                    Actor::Artifact(art) => (art.position, influences.1[art.artifact_index.0]),
+            for other in influencers {
+                let ydist = match other.behavior.mode.pull(other.position.y, b.position.y) {
+                    Some(ydist) => ydist,
+                    None => continue,
                 };
-
-                if influence_factor == 0.0 {
-                    continue;
-                }
-
-                let otherpos2d = Vector2::new(otherpos.x, otherpos.z);
+                let otherpos2d = Vector2::new(other.position.x, other.position.z);
                 let xzdist = bpos.distance(otherpos2d);
-                let influence = 1.0 / (1.0 + xzdist).powf(influence_factor);
-                let ydist = otherpos.y - b.position.y;
+                let influence = other.behavior.falloff.weight(other.factor, xzdist);
 
+                // Normalized by the absolute weights, so pushing and pulling actors can not
+                // cancel out the divisor
                 if !(influence.is_nan() || ydist.is_nan()) {
-                    influecers += influence;
+                    influecers += influence.abs();
                     avg_ydist += ydist * influence;
                 }
             }
@@ -227,31 +328,50 @@ impl World for ChunkedWorld {
             b.position.y += vel;
         }
 
-        let spacing = self.terrain.spacing;
+        let behavior = |behaviors: &[InfluenceBehavior], index: usize| {
+            behaviors.get(index).copied().unwrap_or_default()
+        };
+        let agents = self.get_all_agents().map(|ag| Influencer {
+            position: ag.position,
+            factor: influences.0[ag.species_index.0],
+            behavior: behavior(behaviors.0, ag.species_index.0),
+        });
+        let influencers: Vec<Influencer> = self
+            .get_all_artifacts()
+            .map(|art| Influencer {
+                position: art.position,
+                factor: influences.1[art.artifact_index.0],
+                behavior: behavior(behaviors.1, art.artifact_index.0),
+            })
+            .chain(agents)
+            .filter(|influencer| influencer.factor != 0.0)
+            .collect();
+
         let buoys: Vec<&mut Buoy> = self
             .terrain
             .sample_points
             .iter_mut()
             .flat_map(|v| v.iter_mut())
             .collect();
-        let agents = self
-            .agent_cells
-            .values()
-            .flat_map(|cell| cell.iter())
-            .filter(|ag| influences.0[ag.species_index.0] != 0.0)
-            .map(|ag| Actor::Agent(ag.clone()));
-        let all_actors: Vec<Actor> = self
-            .artifact_cells
-            .values()
-            .flat_map(|cell| cell.iter())
-            .filter(|art| influences.1[art.artifact_index.0] != 0.0)
-            .map(|art| Actor::Artifact(art.clone()))
-            .chain(agents)
-            .collect();
 
-        buoys
-            .into_par_iter()
-            .for_each(|b| update_buoy(&all_actors, b, spacing, influences));
+        let anchor = self.terrain.anchor;
+        match update {
+            TerrainUpdate::Exact => buoys
+                .into_par_iter()
+                .for_each(|b| update_buoy(b, anchor, influencers.iter())),
+            TerrainUpdate::CutOff(_) => {
+                let grid = InfluenceGrid::new(influencers, self.terrain.spacing, update);
+                buoys
+                    .into_par_iter()
+                    .for_each(|b| update_buoy(b, anchor, grid.near(b.position)))
+            }
+        }
+    }
+
+    fn erode_terrain(&mut self, erosion: &TerrainErosion) {
+        if erosion.is_enabled() {
+            self.terrain.erode(erosion);
+        }
     }
 
     fn get_height(&self, agent: &Agent) -> f32 {
@@ -269,13 +389,35 @@ impl World for ChunkedWorld {
         self.terrain.get_slope(xpos, zpos, gradient)
     }
 
-    fn get_size(&self) -> (usize, usize, f32) {
+    fn get_size(&self) -> (usize, usize, f32, (f32, f32)) {
         (
             self.terrain.x_size,
             self.terrain.z_size,
             self.terrain.spacing,
+            self.terrain.origin,
         )
     }
+
+    fn get_obstacles(&self) -> &[Obstacle] {
+        &self.obstacles
+    }
+
+    fn get_fields(&self) -> &[ScalarField] {
+        &self.fields
+    }
+
+    fn update_fields(&mut self, genome: &SwarmGenome) {
+        let mut fields = std::mem::take(&mut self.fields);
+        for agent in self.get_all_agents() {
+            for interaction in &genome.get_species(agent).fields {
+                if interaction.deposit != 0.0 {
+                    fields[interaction.field].deposit(agent.position, interaction.deposit);
+                }
+            }
+        }
+        fields.par_iter_mut().for_each(ScalarField::decay);
+        self.fields = fields;
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -284,6 +426,11 @@ pub struct Terrain {
     spacing: f32,
     x_size: usize,
     z_size: usize,
+    #[serde(default)]
+    origin: (f32, f32),
+    /// Weight of the pull of every buoy back to its base height.
+    #[serde(default)]
+    anchor: f32,
 }
 
 impl Terrain {
@@ -304,16 +451,16 @@ impl Terrain {
         }
     }
 
-    fn new(size: usize, spacing: f32) -> Self {
-        let mut sample_points = Vec::with_capacity(size);
-        for x in 0..size {
-            sample_points.push(Vec::with_capacity(size));
-            for z in 0..size {
+    fn new(x_size: usize, z_size: usize, spacing: f32, origin: (f32, f32)) -> Self {
+        let mut sample_points = Vec::with_capacity(x_size);
+        for x in 0..x_size {
+            sample_points.push(Vec::with_capacity(z_size));
+            for z in 0..z_size {
                 sample_points[x].push(Buoy::new(
                     Vector3::new(
-                        Terrain::translate_back(x) as f32 * spacing,
+                        origin.0 + Terrain::translate_back(x) as f32 * spacing,
                         0.0,
-                        Terrain::translate_back(z) as f32 * spacing,
+                        origin.1 + Terrain::translate_back(z) as f32 * spacing,
                     ),
                     0.0,
                     0.0,
@@ -323,8 +470,67 @@ impl Terrain {
         Terrain {
             sample_points,
             spacing,
-            x_size: size,
-            z_size: size,
+            x_size,
+            z_size,
+            origin,
+            anchor: 0.0,
+        }
+    }
+
+    /// Sets every buoy to the height of `heightmap` stretched over the whole grid and anchors
+    /// it there.
+    fn apply_heightmap(&mut self, heightmap: &Heightmap, config: &HeightmapConfig) {
+        fn relative(index: usize, size: usize) -> f32 {
+            let min = -(((size - 1) / 2) as i64);
+            if size > 1 {
+                (Terrain::translate_back(index) - min) as f32 / (size - 1) as f32
+            } else {
+                0.0
+            }
+        }
+
+        let (x_size, z_size) = (self.x_size, self.z_size);
+        for (x, column) in self.sample_points.iter_mut().enumerate() {
+            for (z, buoy) in column.iter_mut().enumerate() {
+                let sample = heightmap.sample(relative(x, x_size), relative(z, z_size));
+                let height = config.offset + config.scale * sample;
+                buoy.position.y = height;
+                buoy.base = height;
+            }
+        }
+        self.anchor = config.anchor;
+    }
+
+    /// Index of the buoy at `position` along an axis of `size` buoys, counted from the
+    /// lowest coordinate.
+    fn index_by_position(position: usize, size: usize) -> usize {
+        let min = -(((size - 1) / 2) as i64);
+        Terrain::translate(min + position as i64)
+    }
+
+    fn erode(&mut self, erosion: &TerrainErosion) {
+        let (x_size, z_size) = (self.x_size, self.z_size);
+        let index = |i: usize| {
+            (
+                Terrain::index_by_position(i / z_size, x_size),
+                Terrain::index_by_position(i % z_size, z_size),
+            )
+        };
+
+        let mut grid = HeightGrid {
+            heights: (0..x_size * z_size)
+                .map(index)
+                .map(|(x, z)| self.sample_points[x][z].position.y)
+                .collect(),
+            x_size,
+            z_size,
+            spacing: self.spacing,
+        };
+        grid.erode(erosion);
+
+        for (i, height) in grid.heights.into_iter().enumerate() {
+            let (x, z) = index(i);
+            self.sample_points[x][z].position.y = height;
         }
     }
 
@@ -374,11 +580,11 @@ impl Terrain {
         }
 
         //dbg!(xpos);
-        let x_grid = xpos / self.spacing;
+        let x_grid = (xpos - self.origin.0) / self.spacing;
         let x_low = x_grid.floor() as i64;
         let x_high = x_grid.ceil() as i64;
 
-        let z_grid = zpos / self.spacing;
+        let z_grid = (zpos - self.origin.1) / self.spacing;
         let z_low = z_grid.floor() as i64;
         let z_high = z_grid.ceil() as i64;
 
@@ -409,29 +615,19 @@ impl Terrain {
         result
     }
 
-    fn get_height_on_grid(&self, xpos: i64, zpos: i64) -> f32 {
-        let xindex = Terrain::translate(xpos);
-        let zindex = Terrain::translate(zpos);
-
-        let xindex_clamped = if xindex < self.x_size {
-            xindex
-        } else if xindex % 2 == 0 {
-            self.x_size - 1
-        } else {
-            self.x_size - 2
-        };
+    /// Index of the buoy at the grid position `pos` along an axis of `size` buoys, or of the
+    /// outermost buoy in its direction if `pos` is off the grid.
+    fn clamped_index(pos: i64, size: usize) -> usize {
+        let min = -(((size - 1) / 2) as i64);
+        let max = (size / 2) as i64;
+        Terrain::translate(pos.clamp(min, max))
+    }
 
-        let zindex_clamped = if zindex < self.z_size {
-            zindex
-        } else if zindex % 2 == 0 {
-            self.z_size - 1
-        } else {
-            self.z_size - 2
-        };
+    fn get_height_on_grid(&self, xpos: i64, zpos: i64) -> f32 {
+        let xindex = Terrain::clamped_index(xpos, self.x_size);
+        let zindex = Terrain::clamped_index(zpos, self.z_size);
 
-        self.sample_points[xindex_clamped][zindex_clamped]
-            .position
-            .y
+        self.sample_points[xindex][zindex].position.y
     }
 }
 
@@ -443,8 +639,23 @@ pub struct ChunkedWorld {
     agent_count: usize,
     artifact_count: usize,
     buoy_count: usize,
-    spacing: f32,
+    #[serde(alias = "spacing")]
+    cell_size: f32,
+    #[serde(default)]
+    overflow: CellOverflow,
+    #[serde(default)]
+    escaped_agents: usize,
+    #[serde(default)]
+    escaped_artifacts: usize,
+    #[serde(default)]
+    obstacles: Vec<Obstacle>,
+    #[serde(default)]
+    fields: Vec<ScalarField>,
+    #[serde(default)]
+    iteration: usize,
     uid_gen: UidGen,
+    #[serde(skip)]
+    crossing_agents: Vec<Agent>,
 }
 
 use rayon::prelude::*;
@@ -462,77 +673,178 @@ impl ChunkedWorld {
     pub fn get_agents_at_least_within(
         &self,
         range: f32,
-        center_pos: Vector2<f32>,
+        center_pos: Vector3<f32>,
     ) -> impl Iterator<Item = &Agent> {
-        self.agent_cells
-            .iter()
-            .filter(move |(&cell_pos, _)| self.is_cell_included(range, *cell_pos, center_pos))
-            .flat_map(|(_, cell)| cell.iter())
+        cells_within(&self.agent_cells, self.cell_size, range, center_pos)
     }
-    #[allow(dead_code)]
-    fn get_artifacts_at_least_within(
+
+    pub fn get_artifacts_at_least_within(
         &self,
         range: f32,
-        center_pos: Vector2<f32>,
+        center_pos: Vector3<f32>,
     ) -> impl Iterator<Item = &Artifact> {
-        self.artifact_cells
+        cells_within(&self.artifact_cells, self.cell_size, range, center_pos)
+    }
+
+    /// Picks one of the applicable rules of `agent` according to their weights and returns it
+    /// together with its index.
+    fn select_rule<'a>(
+        &self,
+        agent: &Agent,
+        genome: &'a SwarmGenome,
+        rnd: &mut impl Rng,
+    ) -> Option<(usize, &'a ContextRule)> {
+        let rules = genome.get_rules(&agent.species_index);
+
+        let max_range = rules
+            .iter()
+            .map(ContextRule::max_range)
+            .max_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+
+        let context: Vec<(f32, SurroundingIndex)> = if let Some(range) = max_range {
+            self.get_context_within(range, agent.position)
+                .into_iter()
+                .map(|(d, act)| (d, act.into()))
+                .collect()
+        } else {
+            vec![]
+        };
+
+        let height = self.get_height(agent);
+        let applicable_rules: Vec<_> = rules
             .iter()
-            .filter(move |(&cell_pos, _)| self.is_cell_included(range, *cell_pos, center_pos))
-            .flat_map(|(_, cell)| cell.iter())
+            .enumerate()
+            .filter(|(_, rule)| rule.is_allowed_for(agent, height) && rule.is_applicable(&context))
+            .collect();
+
+        let weight_sum: f32 = applicable_rules.iter().map(|(_, rule)| rule.weight).sum();
+        let threshold = rnd.gen_range(0.0, weight_sum + f32::EPSILON);
+
+        let mut gauge = 0.0;
+        applicable_rules.into_iter().find(|(_, rule)| {
+            gauge += rule.weight;
+            gauge >= threshold
+        })
     }
 
-    fn is_cell_included(&self, range: f32, cell_pos: (i16, i16), center_pos: Vector2<f32>) -> bool {
-        // improve filter!
-        let cell_range = (range / self.spacing).ceil() as i16;
+    pub fn apply_heightmap(&mut self, heightmap: &Heightmap, config: &HeightmapConfig) {
+        self.terrain.apply_heightmap(heightmap, config);
+    }
 
-        let x_up = (center_pos.x / self.spacing).floor() as i16 + cell_range;
-        let x_low = (center_pos.x / self.spacing).floor() as i16 - cell_range;
-        let y_up = (center_pos.y / self.spacing).floor() as i16 + cell_range;
-        let y_low = (center_pos.y / self.spacing).floor() as i16 - cell_range;
+    pub fn set_obstacles(&mut self, obstacles: Vec<Obstacle>) {
+        self.obstacles = obstacles;
+    }
 
-        let x_bool = x_low <= cell_pos.0 && cell_pos.0 <= x_up;
-        let y_bool = y_low <= cell_pos.1 && cell_pos.1 <= y_up;
-        x_bool && y_bool
+    /// Replaces the scalar fields by empty fields spanning the terrain.
+    pub fn init_fields(&mut self, configs: &[FieldConfig]) {
+        let (x_size, z_size, spacing, origin) = self.get_size();
+        let min = |size: usize, origin: f32| origin - ((size - 1) / 2) as f32 * spacing;
+        let extent = |size: usize| (size - 1) as f32 * spacing;
+        self.fields = configs
+            .iter()
+            .map(|config| {
+                ScalarField::new(
+                    config,
+                    (min(x_size, origin.0), min(z_size, origin.1)),
+                    (extent(x_size), extent(z_size)),
+                    spacing,
+                )
+            })
+            .collect();
     }
 
-    fn insert_agent(&mut self, agent: Agent) {
-        let x_coord = (agent.position.x / self.spacing).floor() as i16;
-        let y_coord = (agent.position.y / self.spacing).floor() as i16;
+    /// Number of agents which were removed because they left the representable world.
+    pub fn get_escaped_agent_count(&self) -> usize {
+        self.escaped_agents
+    }
+
+    /// Number of artifacts which were removed because they left the representable world.
+    pub fn get_escaped_artifact_count(&self) -> usize {
+        self.escaped_artifacts
+    }
+
+    /// Number of completed steps.
+    pub fn get_iteration(&self) -> usize {
+        self.iteration
+    }
+
+    pub(crate) fn finish_iteration(&mut self) {
+        self.iteration += 1;
+    }
 
-        let cell = self
-            .agent_cells
-            .entry((x_coord, y_coord).into())
-            .or_insert_with(Vec::new);
-        cell.push(agent);
-        self.agent_count += 1;
+    fn insert_agent(&mut self, agent: Agent) {
+        match Coord::target(agent.position, self.cell_size, self.overflow) {
+            Some(coord) => {
+                let cell = self.agent_cells.entry(coord).or_default();
+                cell.push(agent);
+                self.agent_count += 1;
+            }
+            None => self.escaped_agents += 1,
+        }
     }
 
     fn insert_artifact(&mut self, artifact: Artifact) {
-        let x_coord = (artifact.position.x / self.spacing).floor() as i16;
-        let y_coord = (artifact.position.y / self.spacing).floor() as i16;
+        match Coord::target(artifact.position, self.cell_size, self.overflow) {
+            Some(coord) => {
+                let cell = self.artifact_cells.entry(coord).or_default();
+                cell.push(artifact);
+                self.artifact_count += 1;
+            }
+            None => self.escaped_artifacts += 1,
+        }
+    }
 
-        let cell = self
-            .artifact_cells
-            .entry((x_coord, y_coord).into())
-            .or_insert_with(Vec::new);
-        cell.push(artifact);
-        self.artifact_count += 1;
+    /// Sorts all actors into the cells matching their position, e.g. after loading a
+    /// world which was chunked with another layout.
+    pub fn rechunk(&mut self) {
+        let agents: Vec<Agent> = self.get_all_agents().cloned().collect();
+        let artifacts: Vec<Artifact> = self.get_all_artifacts().cloned().collect();
+        self.set_agents(agents);
+        self.set_artifacts(artifacts);
     }
+    // Cells are only emptied to reuse their allocations
     fn delete_agents(&mut self) {
-        self.agent_cells = FnvHashMap::default();
+        self.agent_cells.values_mut().for_each(Vec::clear);
         self.agent_count = 0;
     }
     fn delete_artifacts(&mut self) {
-        self.artifact_cells = FnvHashMap::default();
+        self.artifact_cells.values_mut().for_each(Vec::clear);
         self.artifact_count = 0;
     }
 
-    pub fn new(agents: Vec<Agent>, size: usize, spacing: f32, uid_gen: UidGen) -> ChunkedWorld {
+    /// Drops empty cells once they outnumber the occupied ones.
+    fn prune_cells(&mut self) {
+        fn prune<T>(cells: &mut FnvHashMap<Coord, Vec<T>>) {
+            let empty = cells.values().filter(|cell| cell.is_empty()).count();
+            if empty > cells.len() - empty {
+                cells.retain(|_, cell| !cell.is_empty());
+            }
+        }
+        prune(&mut self.agent_cells);
+        prune(&mut self.artifact_cells);
+    }
+
+    pub fn new(
+        agents: Vec<Agent>,
+        terrain_size: (usize, usize),
+        spacing: f32,
+        origin: (f32, f32),
+        cell_size: f32,
+        overflow: CellOverflow,
+        uid_gen: UidGen,
+    ) -> ChunkedWorld {
         let mut world = ChunkedWorld {
-            spacing,
+            cell_size,
+            overflow,
+            escaped_agents: 0,
+            escaped_artifacts: 0,
+            obstacles: Vec::new(),
+            fields: Vec::new(),
+            iteration: 0,
+            crossing_agents: Vec::new(),
             agent_cells: FnvHashMap::default(),
             artifact_cells: FnvHashMap::default(),
-            terrain: Terrain::new(size, spacing),
+            terrain: Terrain::new(terrain_size.0, terrain_size.1, spacing, origin),
             artifact_count: 0,
             agent_count: 0,
             buoy_count: 0,
@@ -545,6 +857,137 @@ impl ChunkedWorld {
     }
 }
 
+/// Iterates over the content of all cells which may contain an actor within `range` of
+/// `center_pos`. Depending on which is cheaper either the covered cell coordinates are looked
+/// up or the occupied cells are filtered.
+fn cells_within<'a, T>(
+    cells: &'a FnvHashMap<Coord, Vec<T>>,
+    cell_size: f32,
+    range: f32,
+    center_pos: Vector3<f32>,
+) -> Box<dyn Iterator<Item = &'a T> + 'a> {
+    let cell_range = (range / cell_size).ceil() as i32;
+
+    let Coord((x_center, y_center, z_center)) = Coord::clamped(center_pos, cell_size);
+    let (x_low, x_up) = (
+        x_center.saturating_sub(cell_range),
+        x_center.saturating_add(cell_range),
+    );
+    let (y_low, y_up) = (
+        y_center.saturating_sub(cell_range),
+        y_center.saturating_add(cell_range),
+    );
+    let (z_low, z_up) = (
+        z_center.saturating_sub(cell_range),
+        z_center.saturating_add(cell_range),
+    );
+
+    let covered_cells = (x_up as i64 - x_low as i64 + 1)
+        .saturating_mul(y_up as i64 - y_low as i64 + 1)
+        .saturating_mul(z_up as i64 - z_low as i64 + 1);
+
+    if covered_cells > cells.len() as i64 {
+        Box::new(
+            cells
+                .iter()
+                .filter(move |(coord, _)| {
+                    let (x, y, z) = coord.0;
+                    (x_low..=x_up).contains(&x)
+                        && (y_low..=y_up).contains(&y)
+                        && (z_low..=z_up).contains(&z)
+                })
+                .flat_map(|(_, cell)| cell.iter()),
+        )
+    } else {
+        Box::new(
+            (x_low..=x_up)
+                .flat_map(move |x| (y_low..=y_up).map(move |y| (x, y)))
+                .flat_map(move |(x, y)| (z_low..=z_up).map(move |z| Coord((x, y, z))))
+                .filter_map(move |coord| cells.get(&coord))
+                .flat_map(|cell| cell.iter()),
+        )
+    }
+}
+
+/// An actor which shapes the terrain.
+struct Influencer {
+    position: Vector3<f32>,
+    factor: f32,
+    behavior: InfluenceBehavior,
+}
+
+/// Influencers bucketed by their horizontal position, so every buoy only visits the
+/// influencers within their cut-off radius.
+struct InfluenceGrid {
+    buckets: FnvHashMap<(i32, i32), Vec<(Influencer, f32)>>,
+    bucket_size: f32,
+    max_radius: f32,
+}
+
+impl InfluenceGrid {
+    fn new(influencers: Vec<Influencer>, bucket_size: f32, update: TerrainUpdate) -> Self {
+        let mut buckets: FnvHashMap<(i32, i32), Vec<(Influencer, f32)>> = FnvHashMap::default();
+        let mut max_radius: f32 = 0.0;
+
+        for influencer in influencers {
+            let radius = influencer.behavior.falloff.reach(influencer.factor, update);
+            max_radius = max_radius.max(radius);
+            let bucket = Self::bucket_of(influencer.position.x, influencer.position.z, bucket_size);
+            buckets
+                .entry(bucket)
+                .or_default()
+                .push((influencer, radius));
+        }
+
+        InfluenceGrid {
+            buckets,
+            bucket_size,
+            max_radius,
+        }
+    }
+
+    fn bucket_of(x: f32, z: f32, bucket_size: f32) -> (i32, i32) {
+        (
+            (x / bucket_size).floor() as i32,
+            (z / bucket_size).floor() as i32,
+        )
+    }
+
+    /// Influencers which reach the horizontal position of `center`.
+    fn near(&self, center: Vector3<f32>) -> impl Iterator<Item = &Influencer> {
+        let (x_min, z_min) = Self::bucket_of(
+            center.x - self.max_radius,
+            center.z - self.max_radius,
+            self.bucket_size,
+        );
+        let (x_max, z_max) = Self::bucket_of(
+            center.x + self.max_radius,
+            center.z + self.max_radius,
+            self.bucket_size,
+        );
+        let covered_buckets = (i64::from(x_max) - i64::from(x_min) + 1)
+            .saturating_mul(i64::from(z_max) - i64::from(z_min) + 1);
+
+        let buckets: Box<dyn Iterator<Item = &Vec<(Influencer, f32)>>> =
+            if !self.max_radius.is_finite() || covered_buckets > self.buckets.len() as i64 {
+                Box::new(self.buckets.values())
+            } else {
+                Box::new((x_min..=x_max).flat_map(move |x| {
+                    (z_min..=z_max).filter_map(move |z| self.buckets.get(&(x, z)))
+                }))
+            };
+
+        let center = Vector2::new(center.x, center.z);
+        buckets
+            .flat_map(|bucket| bucket.iter())
+            .filter(move |(influencer, radius)| {
+                let position = Vector2::new(influencer.position.x, influencer.position.z);
+                center.distance(position) <= *radius
+            })
+            .map(|(influencer, _)| influencer)
+    }
+}
+
 #[test]
 fn test_terrain_map() {
     for i in -100..100 {
@@ -552,16 +995,344 @@ fn test_terrain_map() {
     }
 }
 
+#[test]
+fn test_rectangular_terrain_with_origin() {
+    let mut terrain = Terrain::new(5, 4, 2.0, (100.0, -50.0));
+    terrain
+        .sample_points
+        .iter_mut()
+        .flat_map(|v| v.iter_mut())
+        .for_each(|b| b.position.y = b.position.x + 2.0 * b.position.z);
+
+    let buoys: Vec<_> = terrain.sample_points.iter().flatten().collect();
+    assert_eq!(buoys.len(), 20);
+    for b in buoys {
+        assert!((96.0..=104.0).contains(&b.position.x));
+        assert!((-52.0..=-46.0).contains(&b.position.z));
+        assert_eq!(terrain.get_height(b.position.x, b.position.z), b.position.y);
+    }
+    assert_eq!(terrain.get_height(101.0, -49.0), 101.0 - 98.0);
+}
+
+#[test]
+fn test_even_terrain_clamps_to_nearest_edge() {
+    let mut terrain = Terrain::new(4, 5, 1.0, (0.0, 0.0));
+    terrain
+        .sample_points
+        .iter_mut()
+        .flat_map(|v| v.iter_mut())
+        .for_each(|b| b.position.y = b.position.x);
+
+    // Along x the grid spans -1 to 2
+    assert_eq!(terrain.get_height_on_grid(-2, 0), -1.0);
+    assert_eq!(terrain.get_height_on_grid(-7, 3), -1.0);
+    assert_eq!(terrain.get_height_on_grid(3, -3), 2.0);
+    assert_eq!(terrain.get_height_on_grid(8, 0), 2.0);
+}
+
+#[test]
+fn test_heightmap_spans_terrain() {
+    let heightmap = Heightmap::from_csv("0, 1, 2\n0, 1, 2\n").unwrap();
+    let mut terrain = Terrain::new(5, 4, 2.0, (10.0, 0.0));
+    let config = HeightmapConfig {
+        path: "ramp.csv".into(),
+        scale: 2.0,
+        offset: -1.0,
+        anchor: 1.0,
+    };
+    terrain.apply_heightmap(&heightmap, &config);
+
+    // x runs from 6 to 14, so the heights rise linearly from -1 to 3
+    for b in terrain.sample_points.iter().flatten() {
+        assert_eq!(b.position.y, (b.position.x - 6.0) / 2.0 - 1.0);
+        assert_eq!(b.base, b.position.y);
+    }
+
+    // smoothing keeps a ramp, except at its ends
+    let smoothing = crate::swarm::genome::terrain::Smoothing {
+        strength: 1.0,
+        iterations: 1,
+    };
+    terrain.erode(&TerrainErosion {
+        smoothing: Some(smoothing),
+        ..Default::default()
+    });
+    for b in terrain.sample_points.iter().flatten() {
+        let ramp = (b.position.x - 6.0) / 2.0 - 1.0;
+        match b.position.x as i32 {
+            6 => assert!(b.position.y > ramp),
+            14 => assert!(b.position.y < ramp),
+            _ => assert_eq!(b.position.y, ramp),
+        }
+    }
+}
+
+#[test]
+fn test_context_matches_brute_force() {
+    use crate::swarm::genome::SpeciesIndex;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    let mut rnd = StdRng::seed_from_u64(42);
+    let mut uid_gen = UidGen::default();
+    let agents: Vec<Agent> = (0..500)
+        .map(|_| {
+            let pos = crate::utils::random_one(&mut rnd) * 100.0;
+            Agent::mk_new(pos, pos, 1.0, SpeciesIndex(0), pos, 0, uid_gen.next()).unwrap()
+        })
+        .collect();
+    let world = ChunkedWorld::new(
+        agents.clone(),
+        (3, 3),
+        10.0,
+        (0.0, 0.0),
+        10.0,
+        CellOverflow::Clamp,
+        uid_gen,
+    );
+
+    for range in &[0.5, 7.0, 35.0, 500.0] {
+        for center in agents.iter().take(20).map(|ag| ag.position) {
+            let mut expected: Vec<_> = agents
+                .iter()
+                .filter(|ag| ag.position.distance(center) < *range)
+                .map(|ag| ag.id)
+                .collect();
+            let mut found: Vec<_> = world
+                .get_context_within(*range, center)
+                .iter()
+                .map(|(_, actor)| actor.get_id())
+                .collect();
+            expected.sort();
+            found.sort();
+            assert_eq!(expected, found);
+        }
+    }
+}
+
+#[cfg(test)]
+fn scattered_world(count: usize, rnd: &mut impl Rng) -> ChunkedWorld {
+    use crate::swarm::genome::SpeciesIndex;
+
+    let mut uid_gen = UidGen::default();
+    let agents: Vec<Agent> = (0..count)
+        .map(|_| {
+            let pos = crate::utils::random_one(rnd) * 50.0;
+            Agent::mk_new(pos, pos, 1.0, SpeciesIndex(0), pos, 0, uid_gen.next()).unwrap()
+        })
+        .collect();
+    ChunkedWorld::new(
+        agents,
+        (3, 3),
+        10.0,
+        (0.0, 0.0),
+        5.0,
+        CellOverflow::Clamp,
+        uid_gen,
+    )
+}
+
+#[test]
+fn test_update_agents_moves_between_cells() {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    let mut rnd = StdRng::seed_from_u64(7);
+    let mut world = scattered_world(300, &mut rnd);
+
+    for _ in 0..10 {
+        let moved: Vec<_> = world
+            .get_all_agents()
+            .map(|ag| {
+                let mut moved = ag.clone();
+                moved.position += crate::utils::random_one(&mut rnd) * 8.0;
+                (ag.id, Some(moved))
+            })
+            .collect();
+        world.update_agents(moved);
+
+        assert_eq!(world.get_agent_count(), 300);
+        for (coord, cell) in world.agent_cells.iter() {
+            assert!(cell
+                .iter()
+                .all(|ag| Coord::of(ag.position, world.cell_size) == Some(*coord)));
+        }
+    }
+}
+
+#[test]
+#[should_panic(expected = "Agents have to be updated in the order of get_all_agents!")]
+fn test_update_agents_rejects_reordered_updates() {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    let mut world = scattered_world(300, &mut StdRng::seed_from_u64(7));
+    let mut reordered: Vec<_> = world
+        .get_all_agents()
+        .map(|ag| (ag.id, Some(ag.clone())))
+        .collect();
+    reordered.swap(0, 299);
+    world.update_agents(reordered);
+}
+
+#[test]
+fn test_replacement_independent_of_threads() {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    let (mut grammar, _) = crate::utils::sample_grammar("context_config.json", 5);
+    let uid_gen = *grammar.world.get_uid_gen();
+    let agents: Vec<Agent> = grammar.world.get_all_agents().cloned().collect();
+    let genome = &grammar.genome;
+    let run = |threads: usize| {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        pool.install(|| {
+            let mut rnd = StdRng::seed_from_u64(5);
+            let mut world = ChunkedWorld::new(
+                agents.clone(),
+                genome.get_terrain_size(),
+                genome.terrain_spacing,
+                genome.terrain_origin,
+                genome.get_cell_size(),
+                genome.grid.overflow,
+                uid_gen,
+            );
+            for _ in 0..4 {
+                world.replace_by(genome, &mut rnd);
+            }
+            let mut agents: Vec<Agent> = world.get_all_agents().cloned().collect();
+            agents.sort_by_key(|ag| ag.id);
+            agents
+        })
+    };
+
+    assert_eq!(run(1), run(4));
+}
+
+#[test]
+fn test_influence_grid_matches_brute_force() {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    let mut rnd = StdRng::seed_from_u64(11);
+    let influencers: Vec<Influencer> = (0..400)
+        .map(|_| Influencer {
+            position: crate::utils::random_one(&mut rnd) * 200.0,
+            factor: rnd.gen_range(2.0, 5.0),
+            behavior: InfluenceBehavior::default(),
+        })
+        .collect();
+    let update = TerrainUpdate::CutOff(0.001);
+
+    let key = |inf: &Influencer| (inf.position.x.to_bits(), inf.position.z.to_bits());
+    let centers: Vec<Vector3<f32>> = (0..50)
+        .map(|_| crate::utils::random_one(&mut rnd) * 150.0)
+        .collect();
+    let expected: Vec<Vec<_>> = centers
+        .iter()
+        .map(|center| {
+            let center2d = Vector2::new(center.x, center.z);
+            let mut expected: Vec<_> = influencers
+                .iter()
+                .filter(|inf| {
+                    let pos2d = Vector2::new(inf.position.x, inf.position.z);
+                    pos2d.distance(center2d) <= update.cut_off_radius(inf.factor)
+                })
+                .map(key)
+                .collect();
+            expected.sort_unstable();
+            expected
+        })
+        .collect();
+
+    let grid = InfluenceGrid::new(influencers, 5.0, update);
+    for (center, expected) in centers.iter().zip(expected) {
+        let mut found: Vec<_> = grid.near(*center).map(key).collect();
+        found.sort_unstable();
+        assert_eq!(expected, found);
+    }
+}
+
+#[test]
+fn test_cell_overflow() {
+    use crate::swarm::genome::SpeciesIndex;
+
+    let far_away = vec![
+        Vector3::new(f32::MAX, 0.0, 0.0),
+        Vector3::new(0.0, f32::NAN, 0.0),
+        Vector3::new(0.0, 0.0, -1e30),
+    ];
+
+    for (policy, expected) in &[(CellOverflow::Clamp, 4), (CellOverflow::Remove, 1)] {
+        let mut uid_gen = UidGen::default();
+        let mut positions = far_away.clone();
+        positions.push(Vector3::new(1.0, 2.0, 3.0));
+        let agents = positions
+            .into_iter()
+            .map(|pos| {
+                Agent::mk_new(pos, pos, 1.0, SpeciesIndex(0), pos, 0, uid_gen.next()).unwrap()
+            })
+            .collect();
+        let world = ChunkedWorld::new(agents, (3, 3), 10.0, (0.0, 0.0), 0.001, *policy, uid_gen);
+
+        assert_eq!(world.get_agent_count(), *expected);
+        assert_eq!(world.get_escaped_agent_count(), 4 - *expected);
+        assert_eq!(
+            world
+                .get_context_within(1.0, Vector3::new(1.0, 2.0, 3.0))
+                .len(),
+            1
+        );
+    }
+}
+
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
-struct Coord((i16, i16));
+struct Coord((i32, i32, i32));
+
+impl Coord {
+    /// Cell containing `position`, if the position is finite and its cell index fits into the
+    /// coordinate range.
+    fn of(position: Vector3<f32>, cell_size: f32) -> Option<Coord> {
+        fn index(pos: f32, cell_size: f32) -> Option<i32> {
+            let index = (pos as f64 / cell_size as f64).floor();
+            if index.is_finite() && index >= i32::MIN as f64 && index <= i32::MAX as f64 {
+                Some(index as i32)
+            } else {
+                None
+            }
+        }
 
-impl From<(i16, i16)> for Coord {
-    fn from(coord: (i16, i16)) -> Self {
+        Some(Coord((
+            index(position.x, cell_size)?,
+            index(position.y, cell_size)?,
+            index(position.z, cell_size)?,
+        )))
+    }
+
+    /// Cell an actor at `position` belongs to under the given overflow policy.
+    fn target(position: Vector3<f32>, cell_size: f32, overflow: CellOverflow) -> Option<Coord> {
+        Coord::of(position, cell_size).or_else(|| match overflow {
+            CellOverflow::Clamp => Some(Coord::clamped(position, cell_size)),
+            CellOverflow::Remove => None,
+        })
+    }
+
+    /// Cell containing `position` or the outermost cell in its direction.
+    fn clamped(position: Vector3<f32>, cell_size: f32) -> Coord {
+        // float to int casts saturate and map NaN to zero
+        Coord((
+            (position.x as f64 / cell_size as f64).floor() as i32,
+            (position.y as f64 / cell_size as f64).floor() as i32,
+            (position.z as f64 / cell_size as f64).floor() as i32,
+        ))
+    }
+}
+
+impl From<(i32, i32, i32)> for Coord {
+    fn from(coord: (i32, i32, i32)) -> Self {
         Coord(coord)
     }
 }
 impl Deref for Coord {
-    type Target = (i16, i16);
+    type Target = (i32, i32, i32);
 
     fn deref(&self) -> &Self::Target {
         &self.0
@@ -573,7 +1344,7 @@ impl Serialize for Coord {
     where
         S: serde::Serializer,
     {
-        serializer.serialize_str(&format!("({},{})", self.0 .0, self.0 .1))
+        serializer.serialize_str(&format!("({},{},{})", self.0 .0, self.0 .1, self.0 .2))
     }
 }
 
@@ -590,10 +1361,13 @@ impl<'de> Deserialize<'de> for Coord {
             .strip_suffix(")")
             .unwrap()
             .split(",");
-        let x = ints.next().unwrap().parse::<i16>().unwrap();
-        let y = ints.next().unwrap().parse::<i16>().unwrap();
+        let x = ints.next().unwrap().parse::<i32>().unwrap();
+        let y = ints.next().unwrap().parse::<i32>().unwrap();
+        // Worlds saved before cells were three dimensional only have two components
+        // and have to be rechunked after loading.
+        let z = ints.next().map_or(0, |z| z.parse::<i32>().unwrap());
 
-        Ok(Coord((x, y)))
+        Ok(Coord((x, y, z)))
     }
 
     fn deserialize_in_place<D>(deserializer: D, place: &mut Self) -> Result<(), D::Error>
@@ -605,3 +1379,38 @@ impl<'de> Deserialize<'de> for Coord {
         Ok(())
     }
 }
+
+#[test]
+fn test_species_strategy_overrides_genome() {
+    use crate::swarm::genome::replacement::ApplicationStrategy;
+
+    let (mut grammar, mut rnd) = crate::utils::sample_grammar("context_config.json", 2);
+    let ids = |world: &ChunkedWorld| {
+        let mut ids: Vec<_> = world.get_all_agents().map(|agent| agent.id).collect();
+        ids.sort();
+        ids
+    };
+    let start = ids(&grammar.world);
+
+    // Fires on every step, but never picks an agent
+    for species in grammar.genome.species_map.iter_mut() {
+        species.strategy = Some(ApplicationStrategy {
+            every: 0,
+            offset: 0,
+            probability: 0.0,
+        });
+    }
+    for _ in 0..4 {
+        grammar.genome.tick();
+        assert!(grammar.genome.should_replace());
+        grammar.world.replace_by(&grammar.genome, &mut rnd);
+    }
+    assert_eq!(ids(&grammar.world), start);
+
+    for species in grammar.genome.species_map.iter_mut() {
+        species.strategy.as_mut().unwrap().probability = 1.0;
+    }
+    grammar.genome.tick();
+    let stats = grammar.world.replace_by(&grammar.genome, &mut rnd);
+    assert!(stats.births > 0);
+}
@@ -1,5 +1,10 @@
 use cgmath::{BaseFloat, InnerSpace, Vector3, Zero};
-use rand::Rng;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+#[cfg(test)]
+use crate::swarm::{genome::SwarmGenome, grammar::SwarmGrammar};
+#[cfg(test)]
+use rand::rngs::StdRng;
 
 const MINUS_ONE: Vector3<f32> = Vector3 {
     x: -1.0,
@@ -63,6 +68,18 @@ impl UidGen {
         self.last += 1;
         Uid(self.last)
     }
+
+    /// Returns a generator for the next `count` uids and skips them in this one.
+    pub fn reserve(&mut self, count: usize) -> UidGen {
+        let reserved = *self;
+        self.last += count as u64;
+        reserved
+    }
+}
+
+/// Random number generator for a single actor, independent of the order actors are processed in.
+pub fn actor_rng(seed: u64, uid: Uid) -> SmallRng {
+    SmallRng::seed_from_u64(seed ^ uid.0.wrapping_mul(0x9E37_79B9_7F4A_7C15))
 }
 
 impl Default for UidGen {
@@ -91,3 +108,22 @@ pub fn zero() -> f32 {
 pub fn one() -> f32 {
     1.0
 }
+
+/// Path of a genome in the `samples` directory.
+#[cfg(test)]
+pub fn sample_path(name: &str) -> String {
+    format!("{}/../samples/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+#[cfg(test)]
+pub fn sample_genome(name: &str) -> SwarmGenome {
+    crate::io::genome_from_file(sample_path(name))
+}
+
+/// Grammar of a sample genome, along with the seeded generator it was created with.
+#[cfg(test)]
+pub fn sample_grammar(name: &str, seed: u64) -> (SwarmGrammar, StdRng) {
+    let mut rnd = StdRng::seed_from_u64(seed);
+    let grammar = SwarmGrammar::from(sample_genome(name), &mut rnd);
+    (grammar, rnd)
+}
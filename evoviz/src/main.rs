@@ -419,14 +419,15 @@ fn main() {
             }
 
             let tsize = sg.world.get_size();
+            let torigin = tsize.3;
             let theight = 70.0;
             let mut image_data = vec![0u8; tsize.0 * tsize.1];
             let toffset = (((tsize.0 - 1) / 2) as f32, ((tsize.1 - 1) / 2) as f32);
             for x in 0..tsize.0 {
                 for z in 0..tsize.1 {
                     let height = sg.world.get_height_at(
-                        (x as f32 - toffset.0) * tsize.2,
-                        (z as f32 - toffset.1) * tsize.2,
+                        torigin.0 + (x as f32 - toffset.0) * tsize.2,
+                        torigin.1 + (z as f32 - toffset.1) * tsize.2,
                     );
                     let factor = if height > theight {
                         1.0
@@ -464,7 +465,11 @@ fn main() {
                 mesh = raylib::models::Mesh::gen_mesh_sphere(&thread, 10.0, 32, 32);
                 Vector3::new(0.0, 0.0, 0.0)
             } else {
-                Vector3::new(-toffset.0 * tsize.2, -theight, -toffset.1 * tsize.2)
+                Vector3::new(
+                    torigin.0 - toffset.0 * tsize.2,
+                    -theight,
+                    torigin.1 - toffset.1 * tsize.2,
+                )
             };
 
             mesh.mesh_tangents();
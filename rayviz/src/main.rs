@@ -347,6 +347,7 @@ fn main() {
 
     let mut render_stats = VizStats::new();
     let mut sim_stats = VizStats::new();
+    let mut step_stats = crustswarm::swarm::stats::StepStats::default();
     let mut calc_next = matches.is_present("instant");
     let mut calc_one = false;
 
@@ -380,6 +381,7 @@ fn main() {
     };
 
     let mut iteration = -1;
+    let mut step_printer = crustswarm::swarm::observer::PrintObserver::default();
     let max_iteration = matches
         .value_of("max-iteration")
         .map_or(std::i32::MAX, |i| i.parse::<i32>().unwrap());
@@ -492,7 +494,9 @@ fn main() {
         {
             if calc_next || calc_one {
                 sim_stats.start();
-                sg.step(&mut rnd);
+                if let Some(stats) = sg.step_observed(&mut rnd, &mut step_printer) {
+                    step_stats = stats;
+                }
                 sim_stats.stop();
 
                 iteration += 1;
@@ -500,14 +504,15 @@ fn main() {
         }
 
         let tsize = sg.world.get_size();
+        let torigin = tsize.3;
         let theight = 70.0;
         let mut image_data = vec![0u8; tsize.0 * tsize.1];
         let toffset = (((tsize.0 - 1) / 2) as f32, ((tsize.1 - 1) / 2) as f32);
         for x in 0..tsize.0 {
             for z in 0..tsize.1 {
                 let height = sg.world.get_height_at(
-                    (x as f32 - toffset.0) * tsize.2,
-                    (z as f32 - toffset.1) * tsize.2,
+                    torigin.0 + (x as f32 - toffset.0) * tsize.2,
+                    torigin.1 + (z as f32 - toffset.1) * tsize.2,
                 );
                 let factor = if height > theight {
                     1.0
@@ -545,7 +550,11 @@ fn main() {
             mesh = raylib::models::Mesh::gen_mesh_sphere(&thread, 10.0, 32, 32);
             Vector3::new(0.0, 0.0, 0.0)
         } else {
-            Vector3::new(-toffset.0 * tsize.2, -theight, -toffset.1 * tsize.2)
+            Vector3::new(
+                torigin.0 - toffset.0 * tsize.2,
+                -theight,
+                torigin.1 - toffset.1 * tsize.2,
+            )
         };
 
         mesh.mesh_tangents();
@@ -687,6 +696,7 @@ fn main() {
                     sg.world.get_buoy_count(),
                 );
                 draw_text(&mut d, &font, 5, 5, &stat_info);
+                draw_text(&mut d, &font, 5, 120, &step_stats_to_string(&step_stats));
 
                 //d.draw_text("", 20, 30, 10, Color::DARKGRAY);
                 d.draw_text(
@@ -765,6 +775,38 @@ fn main() {
     }
 }
 
+fn step_stats_to_string(stats: &crustswarm::swarm::stats::StepStats) -> String {
+    let millis = |time: Duration| time.as_micros() as f32 / 1000f32;
+    let mut lines = vec![
+        format!("Repl:  {:6.1}ms", millis(stats.replacement_time)),
+        format!("Move:  {:6.1}ms", millis(stats.movement_time)),
+        format!("Cells: {:6.1}ms", millis(stats.cells_time)),
+        format!("Field: {:6.1}ms", millis(stats.fields_time)),
+        format!("Terr:  {:6.1}ms", millis(stats.terrain_time)),
+        format!("Eros:  {:6.1}ms", millis(stats.erosion_time)),
+        format!(
+            "Born: {:4} Died: {:4}",
+            stats.replacement.births, stats.replacement.deaths
+        ),
+        format!("Out of bounds: {:4}", stats.out_of_bounds),
+    ];
+    lines.extend(
+        stats
+            .agents_per_species
+            .iter()
+            .enumerate()
+            .map(|(i, count)| format!("Species {:2}: {:4}", i, count)),
+    );
+    lines.extend(
+        stats
+            .artifacts_per_type
+            .iter()
+            .enumerate()
+            .map(|(i, count)| format!("Artifact {:2}: {:4}", i, count)),
+    );
+    lines.join("\n")
+}
+
 type Prec = u32;
 const WINDOW: usize = 10;
 const BASE_INFO_WIDTH: usize = 4;
@@ -7,6 +7,7 @@ use crustswarm::swarm::evo::genome::OIDESwarmGenome;
 //};
 //use ndarray::Array2;
 use r_oide::{prelude::*, traits::VecCollector};
+use std::convert::TryFrom;
 use std::env;
 
 fn main() {
@@ -21,6 +22,7 @@ fn main() {
             println!("generate_zero <species_count> <artifact_count> <rule_count>  \nCreates an oide config template with the given sizes\n");
             println!("parametercount <species_count> <artifact_count> <rule_count> \nCounts the number of variable parameters for the given sizes\n");
             println!("convert2csv <source_dir> <target.csv>                        \nCollects a directory of .oide.jsons into a .csv of floats\n");
+            println!("terrain2mesh <some.grammar.json> <target.[pgm|obj|ply]> [subdivisions]\nExports the terrain of a grammar as heightmap or mesh\n");
             println!("hash <some.json>                                             \nReturns the hash of a given configuration (WIP/Faulty...)\n");
         }
         Some("oide2raw") => {
@@ -52,9 +54,14 @@ fn main() {
                 .unwrap_or("converted.oide.json".to_string());
             println!("converting {} to oide template {}", path, target_path);
             let genome = crustswarm::io::genome_from_file(path);
-            let oide_genome = crustswarm::swarm::evo::genome::OIDESwarmGenome::from(&genome);
-            crustswarm::io::oide_genome_to_file(&oide_genome, target_path)
-                .map(|err| println!("Error occured while converting: {:?}", err));
+            let oide_genome = crustswarm::swarm::evo::genome::OIDESwarmGenome::try_from(&genome);
+            match oide_genome {
+                Ok(oide_genome) => {
+                    crustswarm::io::oide_genome_to_file(&oide_genome, target_path)
+                        .map(|err| println!("Error occured while converting: {:?}", err));
+                }
+                Err(err) => println!("Error occured while converting: {}", err),
+            }
         }
         Some("raw2oide") => {
             let path = env::args()
@@ -67,9 +74,14 @@ fn main() {
                 .unwrap_or("converted.oide.json".to_string());
             println!("converting {} to oide template {}", path, target_path);
             let genome = crustswarm::io::raw_genome_from_file(path);
-            let oide_genome = crustswarm::swarm::evo::genome::OIDESwarmGenome::from(&genome);
-            crustswarm::io::oide_genome_to_file(&oide_genome, target_path)
-                .map(|err| println!("Error occured while converting: {:?}", err));
+            let oide_genome = crustswarm::swarm::evo::genome::OIDESwarmGenome::try_from(&genome);
+            match oide_genome {
+                Ok(oide_genome) => {
+                    crustswarm::io::oide_genome_to_file(&oide_genome, target_path)
+                        .map(|err| println!("Error occured while converting: {:?}", err));
+                }
+                Err(err) => println!("Error occured while converting: {}", err),
+            }
         }
         Some("grammar2oide") => {
             let path = env::args()
@@ -83,9 +95,14 @@ fn main() {
             println!("converting {} to oide template {}", path, target_path);
             let grammar = crustswarm::io::grammar_from_file(path);
             let oide_genome =
-                crustswarm::swarm::evo::genome::OIDESwarmGenome::from(&grammar.genome);
-            crustswarm::io::oide_genome_to_file(&oide_genome, target_path)
-                .map(|err| println!("Error occured while converting: {:?}", err));
+                crustswarm::swarm::evo::genome::OIDESwarmGenome::try_from(&grammar.genome);
+            match oide_genome {
+                Ok(oide_genome) => {
+                    crustswarm::io::oide_genome_to_file(&oide_genome, target_path)
+                        .map(|err| println!("Error occured while converting: {:?}", err));
+                }
+                Err(err) => println!("Error occured while converting: {}", err),
+            }
         }
         Some("rebound_oide") => {
             let path = env::args()
@@ -343,6 +360,30 @@ fn main() {
                     .map(|err| println!("Error occured while converting: {:?}", err));
             }
         }
+        Some("terrain2mesh") => {
+            let path = env::args()
+                .skip(2)
+                .next()
+                .expect("Grammar to export required!");
+            let target_path = env::args()
+                .skip(3)
+                .next()
+                .unwrap_or("terrain.obj".to_string());
+            let subdivisions = env::args()
+                .skip(4)
+                .next()
+                .map_or(1, |s| s.parse::<usize>().unwrap());
+            println!("exporting terrain of {} to {}", path, target_path);
+            let grammar = crustswarm::io::grammar_from_file(path);
+            let result = if target_path.ends_with(".pgm") {
+                crustswarm::io::terrain_to_pgm(&grammar.world, subdivisions, &target_path)
+            } else if target_path.ends_with(".ply") {
+                crustswarm::io::terrain_to_ply(&grammar.world, subdivisions, &target_path)
+            } else {
+                crustswarm::io::terrain_to_obj(&grammar.world, subdivisions, &target_path)
+            };
+            result.map(|err| println!("Error occured while exporting: {:?}", err));
+        }
         Some("hash") => {
             let oide_path = env::args()
                 .skip(2)
@@ -351,18 +392,20 @@ fn main() {
             let genome = if oide_path.ends_with(".oide.json") {
                 crustswarm::io::oide_genome_from_file(&oide_path)
             } else if oide_path.ends_with(".grammar.json") {
-                let translated = crustswarm::swarm::evo::genome::OIDESwarmGenome::from(
+                let translated = crustswarm::swarm::evo::genome::OIDESwarmGenome::try_from(
                     &crustswarm::io::grammar_from_file(&oide_path).genome,
-                );
+                )
+                .unwrap();
                 translated.apply_bounds(&OIDESwarmGenome::new(
                     *translated.species_count,
                     *translated.artifact_count,
                     *translated.rule_count,
                 ))
             } else {
-                crustswarm::swarm::evo::genome::OIDESwarmGenome::from(
+                crustswarm::swarm::evo::genome::OIDESwarmGenome::try_from(
                     &crustswarm::io::genome_from_file(&oide_path),
                 )
+                .unwrap()
             };
             println!("{}", genome.my_hash());
         }
@@ -420,7 +463,8 @@ fn oide_genome2() -> Result<(), std::io::Error> {
     use r_oide::prelude::*;
     println!("Cur. Dir: {:?}", std::env::current_dir());
     let base_tree = crustswarm::io::genome_from_file(r"..\experiments\base_tree.json");
-    let base_tree_genome = crustswarm::swarm::evo::genome::OIDESwarmGenome::from(&base_tree);
+    let base_tree_genome =
+        crustswarm::swarm::evo::genome::OIDESwarmGenome::try_from(&base_tree).unwrap();
 
     let new_bound_genome = OIDESwarmGenome::new(
         *base_tree_genome.species_count,
@@ -458,7 +502,8 @@ fn oide_genome3() -> Result<(), std::io::Error> {
     use r_oide::prelude::*;
     println!("Cur. Dir: {:?}", std::env::current_dir());
     let base_tree = crustswarm::io::genome_from_file(r"..\experiments\base_tree.json");
-    let base_tree_genome = crustswarm::swarm::evo::genome::OIDESwarmGenome::from(&base_tree);
+    let base_tree_genome =
+        crustswarm::swarm::evo::genome::OIDESwarmGenome::try_from(&base_tree).unwrap();
 
     let new_bound_genome = OIDESwarmGenome::new(
         *base_tree_genome.species_count,